@@ -0,0 +1,267 @@
+use anyhow::{Context, Result};
+use chrono::{TimeZone, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const AUTHORIZE_URL: &str = "https://www.dropbox.com/oauth2/authorize";
+const API_URL: &str = "https://api.dropboxapi.com";
+
+/// Refresh the access token this many seconds before Dropbox says it expires.
+const EXPIRY_MARGIN: i64 = 300;
+
+/// Supplies bearer tokens to `DropboxClient`.
+pub trait TokenSource: Send + Sync {
+    fn access_token(&self) -> Result<String>;
+    /// Drops the current access token after the server rejected it.
+    /// Returns false if there is no way to get a different one.
+    fn invalidate(&self) -> bool;
+}
+
+/// A fixed access token, e.g. from `DBX_OAUTH_TOKEN` or for a mock server.
+pub struct StaticToken(pub String);
+
+impl TokenSource for StaticToken {
+    fn access_token(&self) -> Result<String> {
+        Ok(self.0.clone())
+    }
+
+    fn invalidate(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Credentials {
+    pub app_key: String,
+    pub refresh_token: String,
+    #[serde(default)]
+    pub access_token: Option<String>,
+    /// Unix time at which `access_token` expires.
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
+
+impl Credentials {
+    fn valid_access_token(&self) -> Option<&String> {
+        match (&self.access_token, self.expires_at) {
+            (Some(token), Some(expires_at))
+                if expires_at - EXPIRY_MARGIN > Utc::now().timestamp() =>
+            {
+                Some(token)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<i64>,
+    refresh_token: Option<String>,
+}
+
+/// Refresh-token based authorization persisted in the credentials file.
+pub struct Authenticator {
+    path: PathBuf,
+    api_url: String,
+    credentials: Mutex<Credentials>,
+}
+
+/// `$XDG_CONFIG_HOME/my-dropbox-controller`, falling back to `~/.config/my-dropbox-controller`.
+pub fn config_dir() -> Result<PathBuf> {
+    let base = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").context("HOME is not set")?).join(".config"),
+    };
+    Ok(base.join("my-dropbox-controller"))
+}
+
+pub fn credentials_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join("credentials.json"))
+}
+
+impl Authenticator {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| {
+            format!(
+                "not logged in (no credentials at {:?}); run `auth login` first",
+                path
+            )
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            api_url: API_URL.to_string(),
+            credentials: Mutex::new(serde_json::from_str(&content)?),
+        })
+    }
+
+    /// Sends token requests to `base_url` instead of api.dropboxapi.com.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.api_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn credentials(&self) -> Credentials {
+        self.credentials.lock().unwrap().clone()
+    }
+
+    /// Runs the PKCE authorization code flow and stores the resulting refresh token at `path`.
+    pub fn login(app_key: &str, path: &Path, base_url: Option<&str>) -> Result<Self> {
+        let api_url = base_url.unwrap_or(API_URL).trim_end_matches('/');
+        let mut verifier = [0u8; 64];
+        SystemRandom::new()
+            .fill(&mut verifier)
+            .map_err(|_| anyhow::anyhow!("failed to generate PKCE code verifier"))?;
+        let code_verifier = BASE64URL_NOPAD.encode(&verifier);
+        let code_challenge =
+            BASE64URL_NOPAD.encode(digest(&SHA256, code_verifier.as_bytes()).as_ref());
+        eprintln!("Open this URL in your browser:");
+        eprintln!(
+            "{}?client_id={}&response_type=code&token_access_type=offline&code_challenge={}&code_challenge_method=S256",
+            AUTHORIZE_URL, app_key, code_challenge
+        );
+        eprintln!();
+        let auth_code = prompt("Then paste the code here")?;
+
+        eprintln!("requesting OAuth2 token");
+        let token = token_request(
+            api_url,
+            &[
+                ("grant_type", "authorization_code"),
+                ("code", &auth_code),
+                ("client_id", app_key),
+                ("code_verifier", &code_verifier),
+            ],
+        )?;
+        let credentials = Credentials {
+            app_key: app_key.to_string(),
+            refresh_token: token
+                .refresh_token
+                .ok_or(anyhow::anyhow!("no refresh token in the token response"))?,
+            access_token: Some(token.access_token),
+            expires_at: token.expires_in.map(|e| Utc::now().timestamp() + e),
+        };
+        save(path, &credentials)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            api_url: api_url.to_string(),
+            credentials: Mutex::new(credentials),
+        })
+    }
+
+    /// Revokes the tokens on the server side and deletes the credentials file.
+    pub fn logout(self) -> Result<()> {
+        match self.access_token() {
+            Ok(token) => {
+                let response = ureq::post(&format!("{}/2/auth/token/revoke", self.api_url))
+                    .set("Authorization", &format!("Bearer {}", token))
+                    .call();
+                if !response.ok() {
+                    eprintln!("failed to revoke token: {}", response.status_line());
+                }
+            }
+            Err(e) => eprintln!("failed to revoke token: {}", e),
+        }
+        fs::remove_file(&self.path)
+            .with_context(|| format!("failed to remove credentials: {:?}", self.path))?;
+        Ok(())
+    }
+
+    pub fn status(&self) -> String {
+        let credentials = self.credentials();
+        let expiry = match (credentials.valid_access_token(), credentials.expires_at) {
+            (Some(_), Some(expires_at)) => {
+                format!("access token valid until {}", Utc.timestamp(expires_at, 0))
+            }
+            _ => "access token expired, will be refreshed on next use".to_string(),
+        };
+        format!(
+            "logged in (app key: {}, credentials: {:?}), {}",
+            credentials.app_key, self.path, expiry
+        )
+    }
+}
+
+impl TokenSource for Authenticator {
+    fn access_token(&self) -> Result<String> {
+        let mut credentials = self.credentials.lock().unwrap();
+        if let Some(token) = credentials.valid_access_token() {
+            return Ok(token.clone());
+        }
+        let token = token_request(
+            &self.api_url,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &credentials.refresh_token),
+                ("client_id", &credentials.app_key),
+            ],
+        )?;
+        credentials.access_token = Some(token.access_token.clone());
+        credentials.expires_at = token.expires_in.map(|e| Utc::now().timestamp() + e);
+        save(&self.path, &credentials)?;
+        Ok(token.access_token)
+    }
+
+    fn invalidate(&self) -> bool {
+        let mut credentials = self.credentials.lock().unwrap();
+        credentials.access_token = None;
+        credentials.expires_at = None;
+        true
+    }
+}
+
+fn token_request(api_url: &str, form: &[(&str, &str)]) -> Result<TokenResponse> {
+    let response = ureq::post(&format!("{}/oauth2/token", api_url)).send_form(form);
+    if let Some(e) = response.synthetic_error() {
+        return Err(anyhow::anyhow!("token request failed: {}", e));
+    }
+    if !response.ok() {
+        let status = response.status_line().to_string();
+        return Err(anyhow::anyhow!(
+            "token request failed: {}: {}",
+            status,
+            response.into_string()?
+        ));
+    }
+    Ok(serde_json::from_str(&response.into_string()?)?)
+}
+
+/// Writes the credentials readable by the current user only.
+fn save(path: &Path, credentials: &Credentials) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("failed to write credentials: {:?}", path))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    serde_json::to_writer_pretty(&mut file, credentials)?;
+    Ok(())
+}
+
+fn prompt(msg: &str) -> Result<String> {
+    eprint!("{}: ", msg);
+    io::stderr().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_owned())
+}
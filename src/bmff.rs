@@ -0,0 +1,267 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Metadata boxes are small; anything bigger is not what we are looking for.
+const MAX_CONTENT_LEN: u64 = 1024 * 1024;
+
+/// A box (atom) of an ISO base media file such as MP4 or QuickTime, with the byte range
+/// of its content.
+#[derive(Debug, Clone, Copy)]
+pub struct BoxHeader {
+    pub kind: [u8; 4],
+    pub start: u64,
+    pub end: u64,
+}
+
+/// The boxes directly inside `start..end`.
+pub fn children<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Result<Vec<BoxHeader>> {
+    let mut boxes = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let (content, box_end) = match size {
+            // Extends to the end of the enclosing box.
+            0 => (pos + 8, end),
+            1 => {
+                let mut large = [0u8; 8];
+                reader.read_exact(&mut large)?;
+                (pos + 16, pos.saturating_add(u64::from_be_bytes(large)))
+            }
+            size => (pos + 8, pos + size),
+        };
+        if box_end < content || box_end > end {
+            Err(anyhow::anyhow!(
+                "box {} at {} overruns its parent",
+                String::from_utf8_lossy(&kind),
+                pos
+            ))?
+        }
+        boxes.push(BoxHeader {
+            kind,
+            start: content,
+            end: box_end,
+        });
+        pos = box_end;
+    }
+    Ok(boxes)
+}
+
+/// The first box of `kind` directly inside `start..end`.
+pub fn find<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    end: u64,
+    kind: &[u8; 4],
+) -> Result<Option<BoxHeader>> {
+    Ok(children(reader, start, end)?
+        .into_iter()
+        .find(|header| &header.kind == kind))
+}
+
+pub fn read_content<R: Read + Seek>(reader: &mut R, header: &BoxHeader) -> Result<Vec<u8>> {
+    let len = header.end - header.start;
+    if len > MAX_CONTENT_LEN {
+        Err(anyhow::anyhow!(
+            "box {} is too large: {} bytes",
+            String::from_utf8_lossy(&header.kind),
+            len
+        ))?
+    }
+    reader.seek(SeekFrom::Start(header.start))?;
+    let mut content = vec![0u8; len as usize];
+    reader.read_exact(&mut content)?;
+    Ok(content)
+}
+
+/// The first `len` bytes of the content, e.g. the UUID of a `uuid` box.
+pub fn read_prefix<R: Read + Seek>(
+    reader: &mut R,
+    header: &BoxHeader,
+    len: usize,
+) -> Result<Vec<u8>> {
+    let mut prefix = vec![0u8; len.min((header.end - header.start) as usize)];
+    reader.seek(SeekFrom::Start(header.start))?;
+    reader.read_exact(&mut prefix)?;
+    Ok(prefix)
+}
+
+/// The fields of `mvhd` we use, as stored: times are seconds since an epoch that depends on
+/// the writer.
+#[derive(Debug, Clone, Copy)]
+pub struct MovieHeader {
+    pub creation_time: u64,
+    pub modification_time: u64,
+    pub timescale: u32,
+    pub duration: u64,
+}
+
+pub fn movie_header<R: Read + Seek>(reader: &mut R, moov: &BoxHeader) -> Result<MovieHeader> {
+    let mvhd = find(reader, moov.start, moov.end, b"mvhd")?.context("no mvhd box")?;
+    parse_movie_header(&read_content(reader, &mvhd)?).context("malformed mvhd box")
+}
+
+fn parse_movie_header(content: &[u8]) -> Option<MovieHeader> {
+    let be32 = |at: usize| -> Option<u64> {
+        let bytes = content.get(at..at + 4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64)
+    };
+    let be64 = |at: usize| -> Option<u64> { Some(be32(at)? << 32 | be32(at + 4)?) };
+    // Version 1 widens the times and the duration to 64 bits.
+    match content.first()? {
+        0 => Some(MovieHeader {
+            creation_time: be32(4)?,
+            modification_time: be32(8)?,
+            timescale: be32(12)? as u32,
+            duration: be32(16)?,
+        }),
+        1 => Some(MovieHeader {
+            creation_time: be64(4)?,
+            modification_time: be64(12)?,
+            timescale: be32(20)? as u32,
+            duration: be64(24)?,
+        }),
+        _ => None,
+    }
+}
+
+/// Width and height of the first visual track, from its `tkhd`.
+pub fn track_dimensions<R: Read + Seek>(
+    reader: &mut R,
+    moov: &BoxHeader,
+) -> Result<Option<(u32, u32)>> {
+    for trak in children(reader, moov.start, moov.end)?
+        .into_iter()
+        .filter(|header| &header.kind == b"trak")
+    {
+        let tkhd = match find(reader, trak.start, trak.end, b"tkhd")? {
+            Some(tkhd) => tkhd,
+            None => continue,
+        };
+        let content = read_content(reader, &tkhd)?;
+        // 16.16 fixed point width and height end the box; version 1 has 64-bit times.
+        let at = if content.first() == Some(&1) { 88 } else { 76 };
+        let fixed = |at: usize| -> Option<u32> {
+            let bytes = content.get(at..at + 4)?;
+            Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) >> 16)
+        };
+        if let (Some(width), Some(height)) = (fixed(at), fixed(at + 4)) {
+            if width > 0 && height > 0 {
+                return Ok(Some((width, height)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// A text atom from `moov/udta`, such as `©day`, in either the QuickTime layout or the
+/// iTunes one with a nested `data` atom.
+pub fn user_data_text<R: Read + Seek>(
+    reader: &mut R,
+    moov: &BoxHeader,
+    kind: &[u8; 4],
+) -> Result<Option<String>> {
+    let udta = match find(reader, moov.start, moov.end, b"udta")? {
+        Some(udta) => udta,
+        None => return Ok(None),
+    };
+    if let Some(atom) = find(reader, udta.start, udta.end, kind)? {
+        let content = read_content(reader, &atom)?;
+        if content.get(4..8) == Some(&b"data"[..]) {
+            return Ok(data_text(&content[8..]));
+        }
+        // 16-bit length and language code, then the text.
+        if content.len() >= 4 {
+            let len = u16::from_be_bytes([content[0], content[1]]) as usize;
+            let text = &content[4..(4 + len).min(content.len())];
+            return Ok(Some(String::from_utf8_lossy(text).into_owned()));
+        }
+    }
+    if let Some(meta) = find(reader, udta.start, udta.end, b"meta")? {
+        let start = meta_children_start(reader, &meta)?;
+        if let Some(ilst) = find(reader, start, meta.end, b"ilst")? {
+            if let Some(atom) = find(reader, ilst.start, ilst.end, kind)? {
+                return item_text(reader, &atom);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The UTF-8 values of QuickTime `moov/meta` items, keyed by name such as
+/// `com.apple.quicktime.creationdate`.
+pub fn quicktime_metadata<R: Read + Seek>(
+    reader: &mut R,
+    moov: &BoxHeader,
+) -> Result<HashMap<String, String>> {
+    let mut values = HashMap::new();
+    let meta = match find(reader, moov.start, moov.end, b"meta")? {
+        Some(meta) => meta,
+        None => return Ok(values),
+    };
+    let start = meta_children_start(reader, &meta)?;
+    let (keys, ilst) = match (
+        find(reader, start, meta.end, b"keys")?,
+        find(reader, start, meta.end, b"ilst")?,
+    ) {
+        (Some(keys), Some(ilst)) => (keys, ilst),
+        _ => return Ok(values),
+    };
+    let keys = read_content(reader, &keys)?;
+    let mut names = Vec::new();
+    // Version and flags, entry count, then (size, namespace, name) entries.
+    let mut pos = 8;
+    while pos + 8 <= keys.len() {
+        let size =
+            u32::from_be_bytes([keys[pos], keys[pos + 1], keys[pos + 2], keys[pos + 3]]) as usize;
+        if size < 8 || pos + size > keys.len() {
+            break;
+        }
+        names.push(String::from_utf8_lossy(&keys[pos + 8..pos + size]).into_owned());
+        pos += size;
+    }
+    for item in children(reader, ilst.start, ilst.end)? {
+        // Items are named by their 1-based index into `keys`.
+        let index = u32::from_be_bytes(item.kind) as usize;
+        if let Some(name) = index.checked_sub(1).and_then(|i| names.get(i)) {
+            if let Some(text) = item_text(reader, &item)? {
+                values.insert(name.clone(), text);
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// QuickTime `meta` holds its children directly; ISO `meta` is a full box with 4 bytes of
+/// version and flags first.
+fn meta_children_start<R: Read + Seek>(reader: &mut R, meta: &BoxHeader) -> Result<u64> {
+    reader.seek(SeekFrom::Start(meta.start))?;
+    let mut head = [0u8; 8];
+    if meta.end - meta.start >= 8 {
+        reader.read_exact(&mut head)?;
+    }
+    Ok(if &head[4..8] == b"hdlr" {
+        meta.start
+    } else {
+        meta.start + 4
+    })
+}
+
+fn item_text<R: Read + Seek>(reader: &mut R, item: &BoxHeader) -> Result<Option<String>> {
+    match find(reader, item.start, item.end, b"data")? {
+        Some(data) => Ok(data_text(&read_content(reader, &data)?)),
+        None => Ok(None),
+    }
+}
+
+/// The content of a `data` atom: type indicator, locale, value. Only UTF-8 values are text.
+fn data_text(content: &[u8]) -> Option<String> {
+    if content.len() < 8 || content[..4] != [0, 0, 0, 1] {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&content[8..]).into_owned())
+}
@@ -1,19 +1,27 @@
-use crate::{digest::dpx_digest, extension::Extension, meta::datetime};
+use crate::{
+    config::DATETIME_FORMAT,
+    digest::dpx_digest,
+    extension::Extension,
+    meta::{datetime_and_model, DateSource},
+    sqlite::{CachedScan, FileStamp, ScanCache},
+};
 use anyhow::{Context, Result};
-use async_recursion::async_recursion;
-use chrono::{Date, DateTime, Local, Utc};
-use chrono::{Duration, NaiveDateTime};
-use futures::future::join_all;
+use chrono_tz::Tz;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader};
 use std::ops::Add;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use thiserror::Error;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::Mutex;
 
 pub fn sort_calc(hashmap: &mut DatetimeExtnameDigests) {
-    for (date, exts) in hashmap {
+    for exts in hashmap.values_mut() {
         exts.pic
             .sort_by(|a, b| (a.name).partial_cmp(&b.name).unwrap());
         exts.mov
@@ -21,17 +29,18 @@ pub fn sort_calc(hashmap: &mut DatetimeExtnameDigests) {
     }
 }
 pub fn sum_calc(hashmap: &DatetimeExtnameDigests) -> u32 {
-    hashmap.iter().fold(0, |acc, (date, exts)| acc + exts.sum)
+    hashmap.values().map(|exts| exts.sum).sum()
 }
 
-// type NameDigest = (String, String);
 #[derive(Debug)]
 pub struct NameDigest {
     pub digest: String,
     pub name: String,
     pub path: String,
+    pub model: Option<String>,
+    pub date_source: DateSource,
+    pub extension: Extension,
 }
-type ExtNameDigests = HashMap<Extension, Vec<NameDigest>>;
 #[derive(Debug, Default)]
 pub struct SumNameDigests {
     pub pic: Vec<NameDigest>,
@@ -42,7 +51,7 @@ impl SumNameDigests {
     fn merge(&mut self, mut other: Self) {
         self.pic.append(&mut other.pic);
         self.mov.append(&mut other.mov);
-        self.sum = self.sum + other.sum;
+        self.sum += other.sum;
     }
 }
 
@@ -61,380 +70,295 @@ impl Add<SumNameDigests> for SumNameDigests {
     }
 }
 
-#[derive(Debug)]
-pub enum CalcMessage {
-    Finish(i32),
-    File(String),
+/// Where reading a file went wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Open,
+    Metadata,
+    Digest,
 }
-pub async fn runner(path: &Path) -> Result<DatetimeExtnameDigests> {
-    println!("calc start: {:?}", path);
-    if !path.is_dir() {
-        Err(anyhow::anyhow!("not directory"))?
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Stage::Open => "open",
+            Stage::Metadata => "metadata",
+            Stage::Digest => "digest",
+        };
+        write!(f, "{}", name)
     }
-    let (mut tx, mut rx) = channel(32);
-    // println!("accm1: path:{:?}", path);
-    let con = tokio::spawn(async move { controller(rx).await });
-    accm(path, tx.clone(), false).await;
+}
 
-    con.await?
-    // con.
-    // tokio::join!(con).0
+/// A file left out of a scan.
+#[derive(Debug, Clone, Error)]
+#[error("{path}: {stage}: {error}")]
+pub struct FileFailure {
+    pub path: String,
+    pub stage: Stage,
+    pub error: String,
 }
 
-async fn controller(mut rx: Receiver<CalcMessage>) -> Result<(DatetimeExtnameDigests)> {
-    let max = 100;
-    let mut total = None;
-    let mut this_total = 0;
-    let mut v = Vec::with_capacity(max);
-    let mut ret = Vec::new();
-    println!("controlle");
-    while let Some(message) = rx.recv().await {
-        // println!("receive");
-        match message {
-            CalcMessage::Finish(t) => {
-                total = Some(t);
-                // break;
-            }
-            CalcMessage::File(path) => {
-                this_total = this_total + 1;
-                v.push(path);
-            }
-        }
-        if v.len() >= max {
-            println!("controlle over");
-            let v2 = v.clone();
-            ret.push(tokio::spawn(async move { calc2(v2) }));
-            v.clear();
-        }
-        match total {
-            Some(t) => {
-                if t == this_total {
-                    let v2 = v.clone();
-                    ret.push(tokio::spawn(async move { calc2(v2) }));
-                    break;
-                }
-            }
-            None => {}
+/// Result of scanning a directory for files to upload.
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub files: DatetimeExtnameDigests,
+    /// Files skipped because we don't upload their type.
+    pub unsupported: Vec<String>,
+    /// Files that couldn't be read.
+    pub failed: Vec<FileFailure>,
+    /// Files whose content doesn't match their extension: (path, extension's type, content's type).
+    pub mismatched: Vec<(String, Extension, Extension)>,
+}
+
+impl Scan {
+    fn merge(&mut self, mut other: Self) {
+        for (datetime, sum_name_digest) in other.files {
+            self.files
+                .entry(datetime)
+                .or_default()
+                .merge(sum_name_digest);
         }
+        self.unsupported.append(&mut other.unsupported);
+        self.failed.append(&mut other.failed);
+        self.mismatched.append(&mut other.mismatched);
     }
-    let results = futures::future::join_all(ret).await;
-    let mut hashmap: DatetimeExtnameDigests = HashMap::new();
-    for dir in results {
-        let result = dir?;
-        for _result in result {
-            for (datetime, sum_name_digest_result) in _result {
-                match hashmap.get_mut(&datetime) {
-                    Some(sum_name_digest) => sum_name_digest.merge(sum_name_digest_result),
-                    None => {
-                        hashmap.insert(datetime, sum_name_digest_result);
-                    }
-                }
-            }
+}
+
+/// Paths waiting for a worker; the walker blocks when it gets this far ahead.
+const PATH_CHANNEL_CAPACITY: usize = 64;
+/// Scanned files waiting for the aggregator.
+const RESULT_CHANNEL_CAPACITY: usize = 64;
+
+type FileResult = std::result::Result<Scan, FileFailure>;
+
+/// Scans `path` recursively; capture times without a recorded offset are taken to be in `zone`.
+/// Unreadable files are reported in `Scan::failed`, or fail the scan if `strict`.
+///
+/// A walker lists files into a bounded channel, workers read each one on the blocking thread
+/// pool, and the results are merged here until every sender is gone.
+pub async fn runner(
+    path: &Path,
+    zone: Tz,
+    strict: bool,
+    cache: Option<Arc<ScanCache>>,
+) -> Result<Scan> {
+    println!("calc start: {:?}", path);
+    if !path.is_dir() {
+        Err(anyhow::anyhow!("not directory"))?
+    }
+    let (path_tx, path_rx) = channel(PATH_CHANNEL_CAPACITY);
+    let (result_tx, mut result_rx) = channel(RESULT_CHANNEL_CAPACITY);
+    let root = path.to_path_buf();
+    let walker_tx = result_tx.clone();
+    tokio::task::spawn_blocking(move || walk(&root, &path_tx, &walker_tx));
+    let path_rx = Arc::new(Mutex::new(path_rx));
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4);
+    for _ in 0..workers {
+        tokio::spawn(worker(
+            path_rx.clone(),
+            result_tx.clone(),
+            zone,
+            cache.clone(),
+        ));
+    }
+    // Only the walker and the workers may keep the channel open.
+    drop(result_tx);
+
+    let mut scan = Scan::default();
+    while let Some(result) = result_rx.recv().await {
+        match result {
+            Ok(file) => scan.merge(file),
+            // Dropping the receiver makes the workers and then the walker stop.
+            Err(failure) if strict => Err(failure)?,
+            Err(failure) => scan.failed.push(failure),
         }
     }
-    Ok(hashmap)
+    // Workers finish in any order.
+    scan.unsupported.sort();
+    scan.failed.sort_by(|a, b| a.path.cmp(&b.path));
+    scan.mismatched.sort_by(|a, b| a.0.cmp(&b.0));
+    println!("calc end: {:?}", path);
+    Ok(scan)
 }
 
-#[async_recursion]
-async fn accm(path: &Path, mut tx: Sender<CalcMessage>, rec: bool) -> Result<(i32)> {
-    println!("accm: path{:?}", path);
-    let mut sum = 0;
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let entry_path = entry.path();
-        match entry_path.is_dir() {
-            true => {
-                sum = sum + accm(&entry_path, tx.clone(), true).await?;
-            }
-            false => {
-                match Extension::from_path(&entry_path) {
-                    Ok(ext) => match ext {
-                        Extension::Jpeg | Extension::Mp4 => ext,
-                        Extension::Other => continue,
-                    },
-                    Err(_) => {
-                        continue;
-                    }
-                };
-                // println!("send: {:?}", entry_path);
-                tx.send(CalcMessage::File(entry_path.display().to_string()))
-                    .await;
-                sum = sum + 1;
-                // .await;
+/// Sends every file under `dir` to the workers; directories that can't be listed are sent
+/// straight to the aggregator. Returns false once nobody is listening.
+fn walk(dir: &Path, paths: &Sender<PathBuf>, results: &Sender<FileResult>) -> bool {
+    let entries =
+        match fs::read_dir(dir).and_then(|entries| entries.collect::<io::Result<Vec<_>>>()) {
+            Ok(entries) => entries,
+            Err(e) => {
+                return results
+                    .blocking_send(Err(FileFailure {
+                        path: dir.display().to_string(),
+                        stage: Stage::Open,
+                        error: e.to_string(),
+                    }))
+                    .is_ok()
             }
+        };
+    for entry in entries {
+        let path = entry.path();
+        let sent = if path.is_dir() {
+            walk(&path, paths, results)
+        } else {
+            // Workers sort out unsupported files, as that takes reading them.
+            paths.blocking_send(path).is_ok()
+        };
+        if !sent {
+            return false;
         }
     }
-    if !rec {
-        tx.send(CalcMessage::Finish(sum)).await;
-    }
-    // println!("accm: path{:?}", path);
-    Ok(sum)
+    true
 }
 
-pub fn calc2(paths: Vec<String>) -> Result<DatetimeExtnameDigests> {
-    let start_time: DateTime<Local> = Local::now();
-    println!("thread start: {}", start_time);
-    let mut hashmap: DatetimeExtnameDigests = HashMap::new();
-    for path in paths {
-        let path = Path::new(&path);
-        let ext = match Extension::from_path(&path) {
-            Ok(ext) => match ext {
-                Extension::Jpeg | Extension::Mp4 => ext,
-                Extension::Other => continue,
-            },
-            Err(_) => {
-                continue;
-            }
+async fn worker(
+    paths: Arc<Mutex<Receiver<PathBuf>>>,
+    results: Sender<FileResult>,
+    zone: Tz,
+    cache: Option<Arc<ScanCache>>,
+) {
+    loop {
+        // Holding the lock only while waiting lets the other workers read meanwhile.
+        let path = match paths.lock().await.recv().await {
+            Some(path) => path,
+            None => return,
         };
-        let mut file = File::open(&path)
-            .with_context(|| format!("failed to open file: {:?}", path.to_str()))?;
-        let mut buff = BufReader::new(&file);
-        let dtime = datetime(&mut buff, &ext)?
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
-        let digest = dpx_digest(&mut buff)?;
         let path_string = path.display().to_string();
-        let filename = path
-            .file_name()
-            .ok_or(anyhow::anyhow!("filename error1"))
-            .and_then(|n| n.to_str().ok_or(anyhow::anyhow!("filename error2")))?
-            .to_string();
-
-        // let name_digest: NameDigest = (filename, digest);
-        let name_digest: NameDigest = NameDigest {
-            digest: digest,
-            path: path_string,
-            name: filename,
-        };
-        match hashmap.get_mut(&dtime) {
-            Some(sum_exts) => match ext {
-                Extension::Jpeg => {
-                    sum_exts.pic.push(name_digest);
-                    sum_exts.sum = sum_exts.sum + 1;
-                }
-                Extension::Mp4 => {
-                    sum_exts.mov.push(name_digest);
-                    sum_exts.sum = sum_exts.sum + 1;
-                }
-                Extension::Other => {}
-            },
-            None => {
-                let mut map: ExtNameDigests = HashMap::new();
-                let sum_name_digests = match ext {
-                    Extension::Jpeg => SumNameDigests {
-                        pic: vec![name_digest],
-                        mov: Vec::new(),
-                        sum: 1,
-                    },
-                    Extension::Mp4 => SumNameDigests {
-                        mov: vec![name_digest],
-                        pic: Vec::new(),
-                        sum: 1,
-                    },
-                    Extension::Other => SumNameDigests::default(),
-                };
-                hashmap.insert(dtime, sum_name_digests);
-            }
+        let cache = cache.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut scan = Scan::default();
+            scan_file(&mut scan, &path, zone, cache.as_deref()).map(|()| scan)
+        })
+        .await
+        // A parser that panics on a broken file loses only that file.
+        .unwrap_or_else(|e| {
+            Err(FileFailure {
+                path: path_string,
+                stage: Stage::Metadata,
+                error: e.to_string(),
+            })
+        });
+        if results.send(result).await.is_err() {
+            return;
         }
     }
-    let end_time: DateTime<Local> = Local::now();
-    println!(
-        "thread finish: {}, duration: {}",
-        start_time,
-        (end_time - start_time).num_seconds()
-    );
-    Ok(hashmap)
 }
 
-pub type DatetimeExtnameDigests = HashMap<String, SumNameDigests>;
-pub async fn calc_starter(path: &Path) -> Result<DatetimeExtnameDigests> {
-    println!("calc start: {:?}", path);
-    if !path.is_dir() {
-        Err(anyhow::anyhow!("not directory"))?
-    }
-    let mut hashmap: DatetimeExtnameDigests = HashMap::new();
-    let mut dirs = Vec::new();
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        match entry.path().is_dir() {
-            true => {
-                dirs.push(tokio::spawn(async move { calc(&entry.path()) }));
-            }
-            false => {
-                let path = &entry.path();
-                let ext = Extension::from_path(&path);
-                let ext = match ext {
-                    Ok(ex) => match ex {
-                        Extension::Jpeg => Extension::Jpeg,
-                        Extension::Mp4 => Extension::Mp4,
-                        Extension::Other => {
-                            continue;
-                        }
-                    },
-                    Err(_) => {
-                        continue;
-                    }
-                };
-                let mut file = File::open(&path)
-                    .with_context(|| format!("failed to open file: {:?}", path.to_str()))?;
-                let mut buff = BufReader::new(&file);
-                let dtime = datetime(&mut buff, &ext)?.to_string();
-                let digest = dpx_digest(&mut buff)?;
-                let path_string = path.display().to_string();
-                let filename = path
-                    .file_name()
-                    .ok_or(anyhow::anyhow!("filename error1"))
-                    .and_then(|n| n.to_str().ok_or(anyhow::anyhow!("filename error2")))?
-                    .to_string();
-                // let name_digest: NameDigest = (filename, digest);
-                let name_digest: NameDigest = NameDigest {
-                    name: filename,
-                    path: path_string,
-                    digest: digest,
-                };
-                match hashmap.get_mut(&dtime) {
-                    Some(sum_exts) => match ext {
-                        Extension::Jpeg => {
-                            sum_exts.pic.push(name_digest);
-                            sum_exts.sum = sum_exts.sum + 1;
-                        }
-                        Extension::Mp4 => {
-                            sum_exts.mov.push(name_digest);
-                            sum_exts.sum = sum_exts.sum + 1;
-                        }
-                        Extension::Other => {}
-                    },
-                    None => {
-                        let mut map: ExtNameDigests = HashMap::new();
-                        let sum_name_digests = match ext {
-                            Extension::Jpeg => SumNameDigests {
-                                pic: vec![name_digest],
-                                mov: Vec::new(),
-                                sum: 1,
-                            },
-                            Extension::Mp4 => SumNameDigests {
-                                mov: vec![name_digest],
-                                pic: Vec::new(),
-                                sum: 1,
-                            },
-                            Extension::Other => SumNameDigests::default(),
-                        };
-                        hashmap.insert(dtime, sum_name_digests);
-                    }
+/// Reads the capture time and digest of one file.
+fn read_file(
+    path: &Path,
+    ext: &Extension,
+    zone: Tz,
+) -> std::result::Result<CachedScan, (Stage, anyhow::Error)> {
+    let file = File::open(path)
+        .with_context(|| format!("failed to open file: {:?}", path.to_str()))
+        .map_err(|e| (Stage::Open, e))?;
+    let mut buff = BufReader::new(&file);
+    let (capture_time, model) =
+        datetime_and_model(path, &mut buff, ext, zone).map_err(|e| (Stage::Metadata, e))?;
+    let digest = dpx_digest(&mut buff).map_err(|e| (Stage::Digest, e))?;
+    Ok(CachedScan {
+        datetime: capture_time.datetime.format(DATETIME_FORMAT).to_string(),
+        digest,
+        model,
+        date_source: capture_time.source,
+        extension: *ext,
+    })
+}
+
+/// Adds one file to `scan`: to `files` if it can be uploaded, else to `unsupported`. Files
+/// unchanged since they were stored in `cache` are not read.
+pub fn scan_file(
+    scan: &mut Scan,
+    path: &Path,
+    zone: Tz,
+    cache: Option<&ScanCache>,
+) -> std::result::Result<(), FileFailure> {
+    let failure = |stage, e: anyhow::Error| FileFailure {
+        path: path.display().to_string(),
+        stage,
+        error: format!("{:#}", e),
+    };
+    let path_string = path.display().to_string();
+    let claimed = Extension::from_path(path).unwrap_or(Extension::Other);
+    // Stamped before reading, so a file changed meanwhile is read again next time.
+    let cached = match cache {
+        Some(cache) => {
+            let stamp = FileStamp::of(path).map_err(|e| failure(Stage::Open, e.into()))?;
+            match cache.get(&path_string, stamp, zone.name()) {
+                Ok(scanned) => Some((cache, stamp, scanned)),
+                Err(e) => {
+                    eprintln!("scan cache: {}: {:#}", path_string, e);
+                    None
                 }
             }
         }
-    }
-    let results = futures::future::join_all(dirs).await;
-    for dir in results {
-        let result = dir?;
-        for _result in result {
-            for (datetime, sum_name_digest_result) in _result {
-                match hashmap.get_mut(&datetime) {
-                    Some(sum_name_digest) => sum_name_digest.merge(sum_name_digest_result),
-                    None => {
-                        hashmap.insert(datetime, sum_name_digest_result);
-                    }
+        None => None,
+    };
+    let scanned = match cached {
+        Some((_, _, Some(scanned))) => scanned,
+        _ => {
+            let ext = Extension::detect(path, claimed).map_err(|e| failure(Stage::Open, e))?;
+            if !ext.is_picture() && !ext.is_movie() {
+                if ext != claimed {
+                    scan.mismatched.push((path_string.clone(), claimed, ext));
+                }
+                scan.unsupported.push(path_string);
+                return Ok(());
+            }
+            let scanned = read_file(path, &ext, zone).map_err(|(stage, e)| failure(stage, e))?;
+            if let Some((cache, stamp, None)) = cached {
+                if let Err(e) = cache.put(&path_string, stamp, zone.name(), &scanned) {
+                    eprintln!("scan cache: {}: {:#}", path_string, e);
                 }
             }
+            scanned
         }
+    };
+    if scanned.extension != claimed {
+        scan.mismatched
+            .push((path_string, claimed, scanned.extension));
     }
-    // let result = calc(&entry.path())?;
-    // for (datetime, sum_name_digest_result) in result {
-    //     match hashmap.get_mut(&datetime) {
-    //         Some(sum_name_digest) => sum_name_digest.merge(sum_name_digest_result),
-    //         None => {}
-    //     }
-    // }
-    println!("calc end: {:?}", path);
-    Ok(hashmap)
+    add_scanned(scan, path, scanned)
 }
-// pub fn calc(path: &Path) -> Result<HashMap<String, Vec<HashMap<Extension, Vec<String>>>>> {
-pub fn calc(path: &Path) -> Result<DatetimeExtnameDigests> {
-    println!("thread start: {:?}", path);
-    if !path.is_dir() {
-        Err(anyhow::anyhow!("not directory"))?
-    }
-    let mut hashmap: DatetimeExtnameDigests = HashMap::new();
 
-    // let mut hashmap: DatetimeExtnameDigests = HashMap::new();
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        match entry.path().is_dir() {
-            true => {
-                let result = calc(&entry.path())?;
-                for (datetime, sum_name_digest_result) in result {
-                    match hashmap.get_mut(&datetime) {
-                        Some(sum_name_digest) => sum_name_digest.merge(sum_name_digest_result),
-                        None => {}
-                    }
-                }
-            }
-            false => {
-                let path = &entry.path();
-                let ext = Extension::from_path(&path);
-                let ext = match ext {
-                    Ok(ex) => match ex {
-                        Extension::Jpeg => Extension::Jpeg,
-                        Extension::Mp4 => Extension::Mp4,
-                        Extension::Other => {
-                            continue;
-                        }
-                    },
-                    Err(_) => {
-                        continue;
-                    }
-                };
-                let mut file = File::open(&path)
-                    .with_context(|| format!("failed to open file: {:?}", path.to_str()))?;
-                let mut buff = BufReader::new(&file);
-                let dtime = datetime(&mut buff, &ext)?.to_string();
-                let digest = dpx_digest(&mut buff)?;
-                let path_string = path.display().to_string();
-                let filename = path
-                    .file_name()
-                    .ok_or(anyhow::anyhow!("filename error1"))
-                    .and_then(|n| n.to_str().ok_or(anyhow::anyhow!("filename error2")))?
-                    .to_string();
-                // let name_digest: NameDigest = (filename, digest);
-                let name_digest: NameDigest = NameDigest {
-                    name: filename,
-                    path: path_string,
-                    digest: digest,
-                };
-                match hashmap.get_mut(&dtime) {
-                    Some(sum_exts) => match ext {
-                        Extension::Jpeg => {
-                            sum_exts.pic.push(name_digest);
-                            sum_exts.sum = sum_exts.sum + 1;
-                        }
-                        Extension::Mp4 => {
-                            sum_exts.mov.push(name_digest);
-                            sum_exts.sum = sum_exts.sum + 1;
-                        }
-                        Extension::Other => {}
-                    },
-                    None => {
-                        let mut map: ExtNameDigests = HashMap::new();
-                        let sum_name_digests = match ext {
-                            Extension::Jpeg => SumNameDigests {
-                                pic: vec![name_digest],
-                                mov: Vec::new(),
-                                sum: 1,
-                            },
-                            Extension::Mp4 => SumNameDigests {
-                                mov: vec![name_digest],
-                                pic: Vec::new(),
-                                sum: 1,
-                            },
-                            Extension::Other => SumNameDigests::default(),
-                        };
-                        hashmap.insert(dtime, sum_name_digests);
-                    }
-                }
-            }
-        }
+/// Adds a file already read to `scan.files`, under its capture time.
+pub fn add_scanned(
+    scan: &mut Scan,
+    path: &Path,
+    scanned: CachedScan,
+) -> std::result::Result<(), FileFailure> {
+    let failure = |stage, e: anyhow::Error| FileFailure {
+        path: path.display().to_string(),
+        stage,
+        error: format!("{:#}", e),
+    };
+    let path_string = path.display().to_string();
+    let filename = path
+        .file_name()
+        .ok_or(anyhow::anyhow!("filename error1"))
+        .and_then(|n| n.to_str().ok_or(anyhow::anyhow!("filename error2")))
+        .map_err(|e| failure(Stage::Open, e))?
+        .to_string();
+    let sum_exts = scan.files.entry(scanned.datetime).or_default();
+    let name_digest = NameDigest {
+        digest: scanned.digest,
+        path: path_string,
+        name: filename,
+        model: scanned.model,
+        date_source: scanned.date_source,
+        extension: scanned.extension,
+    };
+    if scanned.extension.is_picture() {
+        sum_exts.pic.push(name_digest);
+    } else {
+        sum_exts.mov.push(name_digest);
     }
-    println!("thread end: {:?}", path);
-    Ok(hashmap)
+    sum_exts.sum += 1;
+    Ok(())
 }
+
+pub type DatetimeExtnameDigests = HashMap<String, SumNameDigests>;
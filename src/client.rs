@@ -0,0 +1,176 @@
+use crate::auth::TokenSource;
+use dropbox_sdk::client_trait::{
+    Endpoint, HttpClient, HttpRequestResultRaw, ParamsType, Style, UserAuthClient,
+};
+use dropbox_sdk::Error;
+use std::sync::Arc;
+
+const API_URL: &str = "https://api.dropboxapi.com";
+const CONTENT_URL: &str = "https://content.dropboxapi.com";
+
+/// Dropbox HTTP client whose API hosts can be swapped out, e.g. for a local mock server.
+pub struct DropboxClient {
+    auth: Arc<dyn TokenSource>,
+    api_url: String,
+    content_url: String,
+}
+
+impl DropboxClient {
+    pub fn new(auth: Arc<dyn TokenSource>) -> Self {
+        Self {
+            auth,
+            api_url: API_URL.to_string(),
+            content_url: CONTENT_URL.to_string(),
+        }
+    }
+
+    /// Sends both RPC and content requests to `base_url`.
+    pub fn with_base_url(auth: Arc<dyn TokenSource>, base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+        Self {
+            auth,
+            api_url: base_url.to_string(),
+            content_url: base_url.to_string(),
+        }
+    }
+}
+
+/// The Dropbox-API-Arg header has to be ASCII, so escape everything else as JSON does.
+fn escape_header_json(json: &str) -> String {
+    let mut escaped = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            let mut buf = [0; 2];
+            for unit in c.encode_utf16(&mut buf) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    escaped
+}
+
+fn http_error(e: impl std::fmt::Display) -> Error {
+    Error::HttpClient(format!("{}", e).into())
+}
+
+/// Dropbox puts the wait in both the Retry-After header and `error.retry_after`;
+/// the reason, e.g. `too_many_write_operations`, is only in the body.
+fn rate_limited(json: String, retry_after: Option<u32>) -> Error {
+    let body: serde_json::Value = serde_json::from_str(&json).unwrap_or_default();
+    let retry_after_seconds = retry_after
+        .or(body["error"]["retry_after"].as_u64().map(|s| s as u32))
+        .unwrap_or(1);
+    let reason = body["error_summary"]
+        .as_str()
+        .map(|summary| summary.to_string())
+        .unwrap_or(json);
+    Error::RateLimited {
+        reason,
+        retry_after_seconds,
+    }
+}
+
+impl HttpClient for DropboxClient {
+    fn request(
+        &self,
+        endpoint: Endpoint,
+        style: Style,
+        function: &str,
+        params: String,
+        params_type: ParamsType,
+        body: Option<&[u8]>,
+        range_start: Option<u64>,
+        range_end: Option<u64>,
+    ) -> dropbox_sdk::Result<HttpRequestResultRaw> {
+        let base_url = match endpoint {
+            Endpoint::Content => &self.content_url,
+            _ => &self.api_url,
+        };
+        let send = |token: &str| {
+            let mut request = ureq::post(&format!("{}/2/{}", base_url, function));
+            request.set("Authorization", &format!("Bearer {}", token));
+            match style {
+                Style::Rpc => {
+                    request.set(
+                        "Content-Type",
+                        match params_type {
+                            ParamsType::Json => "application/json",
+                            ParamsType::Form => "application/x-www-form-urlencoded",
+                        },
+                    );
+                    request.send_string(&params)
+                }
+                Style::Upload | Style::Download => {
+                    request.set("Dropbox-API-Arg", &escape_header_json(&params));
+                    match (range_start, range_end) {
+                        (Some(start), Some(end)) => {
+                            request.set("Range", &format!("bytes={}-{}", start, end));
+                        }
+                        (Some(start), None) => {
+                            request.set("Range", &format!("bytes={}-", start));
+                        }
+                        (None, Some(end)) => {
+                            request.set("Range", &format!("bytes=-{}", end));
+                        }
+                        (None, None) => {}
+                    }
+                    match body {
+                        Some(body) => {
+                            request.set("Content-Type", "application/octet-stream");
+                            request.send_bytes(body)
+                        }
+                        None => request.call(),
+                    }
+                }
+            }
+        };
+        let mut response = send(&self.auth.access_token().map_err(http_error)?);
+        // The access token may have expired or been revoked since it was issued.
+        if response.status() == 401 && self.auth.invalidate() {
+            response = send(&self.auth.access_token().map_err(http_error)?);
+        }
+        if let Some(e) = response.synthetic_error() {
+            return Err(http_error(e));
+        }
+        if !response.ok() {
+            let code = response.status();
+            let status = response.status_text().to_string();
+            let retry_after = response
+                .header("Retry-After")
+                .and_then(|seconds| seconds.parse().ok());
+            let json = response.into_string().map_err(http_error)?;
+            return Err(match code {
+                429 => rate_limited(json, retry_after),
+                500..=599 => Error::ServerError(json),
+                _ => Error::UnexpectedHttpError { code, status, json },
+            });
+        }
+        match style {
+            Style::Download => {
+                let result_json = response
+                    .header("Dropbox-API-Result")
+                    .ok_or(Error::UnexpectedResponse(
+                        "missing Dropbox-API-Result header",
+                    ))?
+                    .to_string();
+                let content_length = response
+                    .header("Content-Length")
+                    .and_then(|len| len.parse().ok());
+                Ok(HttpRequestResultRaw {
+                    result_json,
+                    content_length,
+                    body: Some(Box::new(response.into_reader())),
+                })
+            }
+            Style::Rpc | Style::Upload => Ok(HttpRequestResultRaw {
+                result_json: response.into_string().map_err(http_error)?,
+                content_length: None,
+                body: None,
+            }),
+        }
+    }
+}
+
+impl UserAuthClient for DropboxClient {}
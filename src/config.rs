@@ -0,0 +1,152 @@
+use crate::auth::config_dir;
+use crate::calc::NameDigest;
+use anyhow::{Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::NaiveDateTime;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The format of the datetime keys `calc2` groups files by.
+pub const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Where uploads go and what they are called, from `config.toml` and CLI overrides.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Remote folder uploads are put under.
+    pub dest_root: String,
+    /// strftime format of the file name, without extension. Besides the `%` specifiers it
+    /// may contain `{counter}` (empty for the first file of a second, then `_1`, `_2`, ...),
+    /// `{name}` (original file name without extension) and `{model}` (camera model).
+    pub name_template: String,
+    /// strftime format of subfolders under `dest_root`, e.g. `%Y/%m`.
+    pub subfolders: Option<String>,
+    /// Local SQLite index of what is already in Dropbox, also holding the scan cache.
+    pub index: String,
+    /// Remote path `db push` and `reset-db` upload the index to and `db pull` fetches it from.
+    pub remote_index: String,
+    /// IANA zone for capture times that don't carry their own offset, e.g. MP4 or
+    /// JPEGs without `OffsetTimeOriginal`.
+    pub timezone: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dest_root: "/カメラアップロード".to_string(),
+            name_template: format!("{}{{counter}}", DATETIME_FORMAT),
+            subfolders: None,
+            index: "my-dropbox.db3".to_string(),
+            remote_index: "/my-dropbox2.db3".to_string(),
+            timezone: "Asia/Tokyo".to_string(),
+        }
+    }
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join("config.toml"))
+}
+
+impl Config {
+    /// Reads `path`; a missing file means the defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        let config: Self = match fs::read_to_string(path) {
+            Ok(content) => {
+                toml::from_str(&content).with_context(|| format!("invalid config: {:?}", path))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => Err(e).with_context(|| format!("failed to read config: {:?}", path))?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.dest_root.starts_with('/') {
+            Err(anyhow::anyhow!(
+                "dest_root must start with '/': {}",
+                self.dest_root
+            ))?
+        }
+        if !self.remote_index.starts_with('/') {
+            Err(anyhow::anyhow!(
+                "remote_index must start with '/': {}",
+                self.remote_index
+            ))?
+        }
+        check_strftime(&self.name_template)?;
+        // Without it, two files taken in the same second could get the same name; `{name}`
+        // alone doesn't help when they come from different cards.
+        if !self.name_template.contains("{counter}") {
+            Err(anyhow::anyhow!(
+                "name_template must contain {{counter}}: {}",
+                self.name_template
+            ))?
+        }
+        if let Some(subfolders) = &self.subfolders {
+            check_strftime(subfolders)?;
+        }
+        self.zone()?;
+        Ok(())
+    }
+
+    pub fn zone(&self) -> Result<Tz> {
+        self.timezone
+            .parse()
+            .map_err(|e| anyhow::anyhow!("invalid timezone: {}", e))
+    }
+
+    /// Remote path of the `counter`th new file taken at `datetime`, a `DATETIME_FORMAT` key.
+    pub fn remote_path(
+        &self,
+        datetime: &str,
+        counter: u32,
+        file: &NameDigest,
+        ext: &str,
+    ) -> Result<String> {
+        let datetime = NaiveDateTime::parse_from_str(datetime, DATETIME_FORMAT)
+            .with_context(|| format!("invalid datetime key: {}", datetime))?;
+        let counter = if counter != 0 {
+            format!("_{}", counter)
+        } else {
+            String::new()
+        };
+        let stem = Path::new(&file.name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(&file.name);
+        let name = datetime
+            .format(&self.name_template)
+            .to_string()
+            .replace("{counter}", &counter)
+            .replace("{name}", &path_component(stem))
+            .replace(
+                "{model}",
+                &path_component(file.model.as_deref().unwrap_or("unknown")),
+            );
+        let mut path = self.dest_root.trim_end_matches('/').to_string();
+        if let Some(subfolders) = &self.subfolders {
+            path = format!(
+                "{}/{}",
+                path,
+                datetime.format(subfolders).to_string().trim_matches('/')
+            );
+        }
+        Ok(format!("{}/{}.{}", path, name, ext))
+    }
+}
+
+/// chrono panics when formatting with an invalid specifier, so reject those up front.
+fn check_strftime(format: &str) -> Result<()> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        Err(anyhow::anyhow!("invalid strftime format: {}", format))?
+    }
+    Ok(())
+}
+
+/// Keeps values taken from the file from adding path levels.
+fn path_component(value: &str) -> String {
+    value.trim().replace('/', "_")
+}
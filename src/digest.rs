@@ -1,26 +1,55 @@
 use anyhow::Result;
+use digest::{FixedOutput, Input};
 use dropbox_content_hasher::DropboxContentHasher;
 use ring::digest::{Context, Digest, SHA256};
 use std::io::Read;
 use std::io::{Seek, SeekFrom};
 
+/// One Dropbox block, so a movie takes few reads.
+const BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// Both hashes of a file, from a single read.
+pub struct Digests {
+    pub sha256: Digest,
+    pub content_hash: String,
+}
+
+pub fn digests<R: Read + Seek>(reader: &mut R) -> Result<Digests> {
+    let mut sha256 = Context::new(&SHA256);
+    let mut content_hasher = DropboxContentHasher::new();
+    hash_reader(reader, |chunk| {
+        sha256.update(chunk);
+        content_hasher.input(chunk);
+    })?;
+    Ok(Digests {
+        sha256: sha256.finish(),
+        content_hash: format!("{:x}", content_hasher.fixed_result()),
+    })
+}
+
 pub fn sha_256_digest<R: Read + Seek>(reader: &mut R) -> Result<Digest> {
     let mut context = Context::new(&SHA256);
-    let mut buffer = [0; 1024];
+    hash_reader(reader, |chunk| context.update(chunk))?;
+    Ok(context.finish())
+}
 
+pub fn dpx_digest<R: Read + Seek>(reader: &mut R) -> Result<String> {
+    let mut hasher = DropboxContentHasher::new();
+    hash_reader(reader, |chunk| hasher.input(chunk))?;
+    Ok(format!("{:x}", hasher.fixed_result()))
+}
+
+/// Feeds the rest of `reader` to `update`, then seeks back to the start. The buffer lives only
+/// as long as the call, so idle blocking-pool threads don't each keep one.
+fn hash_reader<R: Read + Seek>(reader: &mut R, mut update: impl FnMut(&[u8])) -> Result<()> {
+    let mut buffer = vec![0; BUFFER_SIZE];
     loop {
         let count = reader.read(&mut buffer)?;
         if count == 0 {
             break;
         }
-        context.update(&buffer[..count]);
+        update(&buffer[..count]);
     }
     reader.seek(SeekFrom::Start(0))?;
-    Ok(context.finish())
-}
-
-pub fn dpx_digest<R: Read + Seek>(mut reader: &mut R) -> Result<String> {
-    let hash = DropboxContentHasher::hash_reader(&mut reader)?;
-    reader.seek(SeekFrom::Start(0))?;
-    Ok(format!("{:x}", hash))
+    Ok(())
 }
@@ -1,23 +1,30 @@
 use anyhow::Result;
-use chrono::{Date, DateTime, Local, Utc};
-use chrono::{Duration, NaiveDateTime};
-use dropbox_sdk::default_client::{NoauthDefaultClient, UserAuthDefaultClient};
-use dropbox_sdk::oauth2::{
-    oauth2_token_from_authorization_code, Oauth2AuthorizeUrlBuilder, Oauth2Type,
-};
-use dropbox_sdk::{files, UserAuthClient};
-use std::collections::HashMap;
-use std::env;
+use chrono::{DateTime, Local};
+use dropbox_sdk::files;
+use rusqlite::Connection;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::File;
-use std::io::{self, Read, Write};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::Sender;
 use {
     crate::{
+        auth::TokenSource,
         calc::{DatetimeExtnameDigests, SumNameDigests},
-        sqlite::{connection, exist, Message},
+        client::DropboxClient,
+        config::Config,
+        limit::UploadLimiter,
+        retry::RetryPolicy,
+        sqlite::{connection, exist, record_upload, FileStamp, FileType, Message, SessionJournal},
+        store::{
+            FinishBatchStatus, FinishEntry, FinishEntryResult, ListPage, RemoteFile, RemoteStore,
+            SessionLookupError,
+        },
     },
     dropbox_sdk::dbx_async,
     dropbox_sdk::files::{FileMetadata, ListFolderResult, Metadata},
@@ -29,135 +36,66 @@ const PARALLELISM: usize = 20;
 /// The size of a block. This is a Dropbox constant, not adjustable.
 const BLOCK_SIZE: usize = 4 * 1024 * 1024;
 
-pub fn list_directory2(path: &str, tx: Sender<Message>) {
-    // let client = UserAuthDefaultClient::new(get_oauth2_token());
-    let client = UserAuthDefaultClient::new(oauth2());
+/// How long to wait between finish batch status checks.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Lists `path` recursively on a blocking thread, sending its files in order and then the
+/// cursor to continue from.
+pub fn list_directory2(store: Arc<dyn RemoteStore>, path: &str, tx: Sender<Message>) {
     let requested_path = if path == "/" {
         String::new()
     } else {
         path.to_owned()
     };
-    let mut count = 0;
-    match files::list_folder(
-        &client,
-        &files::ListFolderArg::new(requested_path).with_recursive(true),
-    ) {
-        Ok(Ok(ListFolderResult {
+    tokio::task::spawn_blocking(move || {
+        // The receiver going away means nobody is left to tell.
+        let _ = send_pages(store.as_ref(), &requested_path, &tx);
+    });
+}
+
+/// Returns `None` once the receiver has gone.
+fn send_pages(store: &dyn RemoteStore, path: &str, tx: &Sender<Message>) -> Option<()> {
+    let mut page = store.list_folder(path, true);
+    loop {
+        let ListPage {
             entries,
-            mut cursor,
+            cursor,
             has_more,
             ..
-        })) => {
-            let tx2 = tx.clone();
-            tokio::spawn(async move {
-                for meta in entries {
-                    match meta {
-                        Metadata::File(FileMetadata {
-                            name, content_hash, ..
-                        }) => match content_hash {
-                            Some(hash) => {
-                                tx2.send(Message::Progress(name, hash)).await;
-                            }
-                            None => {
-                                tx2.send(Message::Abort(format!(
-                                    "content hash was empty: {}",
-                                    name
-                                )))
-                                .await;
-                            }
-                        },
-                        _ => {}
-                    }
-                }
-                if !has_more {
-                    tx2.send(Message::Finish).await;
-                }
-            });
-
-            if !has_more {
-                return;
+        } = match page {
+            Ok(page) => page,
+            Err(e) => {
+                return tx
+                    .blocking_send(Message::Abort(format!("request failure: {}", e)))
+                    .ok()
             }
-            let mut new_cursor = cursor;
-
-            loop {
-                // println!("fetch count: {}", count);
-                match files::list_folder_continue(
-                    &client,
-                    &files::ListFolderContinueArg::new(new_cursor),
-                ) {
-                    Ok(Ok(ListFolderResult {
-                        entries,
-                        mut cursor,
-                        has_more,
-                        ..
-                    })) => {
-                        new_cursor = cursor;
-                        let tx2 = tx.clone();
-                        tokio::spawn(async move {
-                            for meta in entries {
-                                match meta {
-                                    Metadata::File(FileMetadata {
-                                        name, content_hash, ..
-                                    }) => match content_hash {
-                                        Some(hash) => {
-                                            tx2.send(Message::Progress(name, hash)).await;
-                                        }
-                                        None => {
-                                            tx2.send(Message::Abort(format!(
-                                                "content hash was empty: {}",
-                                                name
-                                            )))
-                                            .await;
-                                            return;
-                                        }
-                                    },
-                                    _ => {}
-                                }
-                            }
-                            if !has_more {
-                                tx2.send(Message::Finish).await;
-                                return;
-                            }
-                        });
-                        if !has_more {
-                            return;
-                        }
-                    }
-                    Ok(Err(e)) => {
-                        println!("{}", e);
-                        tx.send(Message::Abort(format!("request failure: {}", e)));
-                        return;
-                    }
-                    Err(e) => {
-                        println!("{}", e);
-                        tx.send(Message::Abort(format!("request failure: {}", e)));
-                        return;
-                    }
-                }
-                // cursor = "1".to_string();
-                count = count + 1;
+        };
+        for file in entries {
+            if file.content_hash.is_none() {
+                return tx
+                    .blocking_send(Message::Abort(format!(
+                        "content hash was empty: {}",
+                        file.name
+                    )))
+                    .ok();
             }
-            // println!("{:?}", result);
+            tx.blocking_send(Message::Progress(file)).ok()?;
         }
-        Ok(Err(e)) => {
-            println!("{}", e);
-            tx.send(Message::Abort(format!("request failure: {}", e)));
+        if !has_more {
+            return tx.blocking_send(Message::Finish(cursor)).ok();
         }
-        Err(e) => {
-            println!("{}", e);
-            tx.send(Message::Abort(format!("request failure: {}", e)));
-        }
-    };
+        page = store.list_folder_continue(&cursor);
+    }
 }
-pub fn list_directory(path: &str) {
-    let client = UserAuthDefaultClient::new(get_oauth2_token());
+
+pub fn list_directory(client: &DropboxClient, path: &str) {
     let requested_path = if path == "/" {
         String::new()
     } else {
         path.to_owned()
     };
     match files::list_folder(
-        &client,
+        client,
         &files::ListFolderArg::new(requested_path).with_recursive(false),
     ) {
         Ok(Ok(result)) => {
@@ -172,10 +110,9 @@ pub fn list_directory(path: &str) {
     };
 }
 
-pub fn get_file_metadata(path: &str) {
-    let client = UserAuthDefaultClient::new(get_oauth2_token());
+pub fn get_file_metadata(client: &DropboxClient, path: &str) {
     match files::get_metadata(
-        &client,
+        client,
         &files::GetMetadataArg::new(path.to_string()).with_include_media_info(true),
     ) {
         Ok(Ok(result)) => {
@@ -190,51 +127,12 @@ pub fn get_file_metadata(path: &str) {
     };
 }
 
-pub fn get_oauth2_token() -> String {
-    env::var("DBX_OAUTH_TOKEN").unwrap()
-}
-pub fn oauth2() -> String {
-    let client_id = env::var("DBX_API_APP_KEY").expect("need API APP KEY");
-    let client_secret = env::var("DBX_API_APP_SECRET").expect("need API APP SECRET");
-    let url = Oauth2AuthorizeUrlBuilder::new(&client_id, Oauth2Type::AuthorizationCode).build();
-    eprintln!("Open this URL in your browser:");
-    eprintln!("{}", url);
-    eprintln!();
-    let auth_code = prompt("Then paste the code here");
-
-    eprintln!("requesting OAuth2 token");
-
-    match oauth2_token_from_authorization_code(
-        NoauthDefaultClient::default(),
-        &client_id,
-        &client_secret,
-        auth_code.trim(),
-        None,
-    ) {
-        Ok(token) => {
-            eprintln!("got token: {}", token);
-
-            token
-        }
-        Err(e) => {
-            eprintln!("Error getting OAuth2 token: {}", e);
-            std::process::exit(1);
-        }
-    }
-}
-
-fn prompt(msg: &str) -> String {
-    eprint!("{}: ", msg);
-    io::stderr().flush().unwrap();
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
-    input.trim().to_owned()
-}
-
 #[derive(Debug)]
 pub struct Resume {
-    start_offset: u64,
-    session_id: String,
+    pub start_offset: u64,
+    pub session_id: String,
+    /// Blocks past `start_offset` that have already been acknowledged, offset -> length.
+    pub uploaded_blocks: HashMap<u64, u64>,
 }
 
 struct UploadSession {
@@ -243,6 +141,7 @@ struct UploadSession {
     file_size: u64,
     bytes_transferred: AtomicU64,
     completion: Mutex<CompletionTracker>,
+    journal: Option<Arc<SessionJournal>>,
 }
 
 #[derive(Default)]
@@ -251,217 +150,755 @@ struct CompletionTracker {
     uploaded_blocks: HashMap<u64, u64>,
 }
 
+impl CompletionTracker {
+    fn complete_block(&mut self, block_offset: u64, block_len: u64) {
+        if block_offset == self.complete_up_to {
+            self.complete_up_to += block_len;
+            // Blocks that finished out of order may now be contiguous with the low-water mark.
+            while let Some(len) = self.uploaded_blocks.remove(&self.complete_up_to) {
+                self.complete_up_to += len;
+            }
+        } else {
+            self.uploaded_blocks.insert(block_offset, block_len);
+        }
+    }
+}
+
 impl UploadSession {
-    fn new(client: &UserAuthDefaultClient, file_size: u64) -> Result<Self> {
-        let session_id = match files::upload_session_start(
-            client,
-            &files::UploadSessionStartArg::default()
-                .with_session_type(files::UploadSessionType::Concurrent),
-            &[],
-        ) {
-            Ok(result) => match result {
-                Ok(result) => result.session_id,
-                Err(e) => return Err(anyhow::anyhow!(format!("{}", e))),
-            },
-            Err(e) => return Err(anyhow::anyhow!(format!("{}", e))),
-        };
+    fn new(store: &dyn RemoteStore, file_size: u64) -> Result<Self> {
+        let session_id = store.upload_session_start()?;
         Ok(Self {
             session_id,
             start_offset: 0,
             file_size,
             bytes_transferred: AtomicU64::new(0),
             completion: Mutex::new(CompletionTracker::default()),
+            journal: None,
         })
     }
+
+    fn resume(resume: Resume, file_size: u64) -> Self {
+        Self {
+            session_id: resume.session_id,
+            start_offset: resume.start_offset,
+            file_size,
+            bytes_transferred: AtomicU64::new(0),
+            completion: Mutex::new(CompletionTracker {
+                complete_up_to: resume.start_offset,
+                uploaded_blocks: resume.uploaded_blocks,
+            }),
+            journal: None,
+        }
+    }
+
+    /// Continues the journaled session for `path` if there is one, otherwise starts a new one.
+    fn open(
+        store: &dyn RemoteStore,
+        stamp: FileStamp,
+        journal: Option<(Arc<SessionJournal>, &str)>,
+    ) -> Result<Self> {
+        let file_size = stamp.size as u64;
+        let (journal, path) = match journal {
+            Some(journal) => journal,
+            None => return Self::new(store, file_size),
+        };
+        let mut session = match journal.find(path, stamp)? {
+            Some(resume) => {
+                eprintln!(
+                    "resume upload session {} from {}",
+                    resume.session_id, resume.start_offset
+                );
+                Self::resume(resume, file_size)
+            }
+            None => {
+                let session = Self::new(store, file_size)?;
+                journal.start(path, stamp, &session.session_id)?;
+                session
+            }
+        };
+        session.journal = Some(journal);
+        Ok(session)
+    }
+
+    fn is_block_uploaded(&self, block_offset: u64) -> bool {
+        let completion = self.completion.lock().unwrap();
+        block_offset < completion.complete_up_to
+            || completion.uploaded_blocks.contains_key(&block_offset)
+    }
+
+    fn mark_block_uploaded(&self, block_offset: u64, block_len: u64) -> Result<()> {
+        let mut completion = self.completion.lock().unwrap();
+        completion.complete_block(block_offset, block_len);
+        self.bytes_transferred.fetch_add(block_len, SeqCst);
+        if let Some(journal) = &self.journal {
+            journal.update(
+                &self.session_id,
+                completion.complete_up_to,
+                &completion.uploaded_blocks,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `RemoteStore` backed by the Dropbox API.
+pub struct DropboxStore {
+    client: DropboxClient,
+    retry: RetryPolicy,
+    limiter: Option<Arc<UploadLimiter>>,
+}
+
+impl DropboxStore {
+    pub fn new(auth: Arc<dyn TokenSource>) -> Self {
+        Self {
+            client: DropboxClient::new(auth),
+            retry: RetryPolicy::default(),
+            limiter: None,
+        }
+    }
+
+    /// Talks to a Dropbox-compatible server at `base_url` instead of dropboxapi.com.
+    pub fn with_base_url(auth: Arc<dyn TokenSource>, base_url: &str) -> Self {
+        Self {
+            client: DropboxClient::with_base_url(auth, base_url),
+            retry: RetryPolicy::default(),
+            limiter: None,
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Paces every attempt to append a block, retries included, through `limiter`.
+    pub fn with_limiter(mut self, limiter: Arc<UploadLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    fn list_page(result: ListFolderResult) -> ListPage {
+        let ListFolderResult {
+            entries,
+            cursor,
+            has_more,
+            ..
+        } = result;
+        let mut files = Vec::new();
+        let mut deleted = Vec::new();
+        for meta in entries {
+            match meta {
+                Metadata::File(file) => files.push(remote_file(file)),
+                Metadata::Deleted(meta) => {
+                    deleted.push(meta.path_display.or(meta.path_lower).unwrap_or(meta.name))
+                }
+                Metadata::Folder(_) => {}
+            }
+        }
+        ListPage {
+            entries: files,
+            deleted,
+            cursor,
+            has_more,
+        }
+    }
+
+    fn batch_result(result: files::UploadSessionFinishBatchResult) -> FinishBatchStatus {
+        FinishBatchStatus::Complete(
+            result
+                .entries
+                .into_iter()
+                .map(|entry| match entry {
+                    files::UploadSessionFinishBatchResultEntry::Success(file) => {
+                        Ok(remote_file(file))
+                    }
+                    files::UploadSessionFinishBatchResultEntry::Failure(e) => Err(format!("{}", e)),
+                })
+                .collect(),
+        )
+    }
+}
+
+fn remote_file(file: FileMetadata) -> RemoteFile {
+    RemoteFile {
+        path_display: file.path_display.unwrap_or(format!("/{}", file.name)),
+        name: file.name,
+        content_hash: file.content_hash,
+        size: file.size,
+        server_modified: Some(file.server_modified),
+        rev: Some(file.rev),
+    }
+}
+
+impl RemoteStore for DropboxStore {
+    fn list_folder(&self, path: &str, recursive: bool) -> Result<ListPage> {
+        let arg = files::ListFolderArg::new(path.to_string()).with_recursive(recursive);
+        match self.retry.run(|| files::list_folder(&self.client, &arg)) {
+            Ok(Ok(result)) => Ok(Self::list_page(result)),
+            Ok(Err(e)) => Err(anyhow::anyhow!(format!("{}", e))),
+            Err(e) => Err(anyhow::anyhow!(format!("{}", e))),
+        }
+    }
+
+    fn list_folder_continue(&self, cursor: &str) -> Result<ListPage> {
+        let arg = files::ListFolderContinueArg::new(cursor.to_string());
+        match self
+            .retry
+            .run(|| files::list_folder_continue(&self.client, &arg))
+        {
+            Ok(Ok(result)) => Ok(Self::list_page(result)),
+            Ok(Err(files::ListFolderContinueError::Reset)) => Err(anyhow::anyhow!(
+                "the listing cursor has expired; run reset-db"
+            )),
+            Ok(Err(e)) => Err(anyhow::anyhow!(format!("{}", e))),
+            Err(e) => Err(anyhow::anyhow!(format!("{}", e))),
+        }
+    }
+
+    fn upload_session_start(&self) -> Result<String> {
+        let arg = files::UploadSessionStartArg::default()
+            .with_session_type(files::UploadSessionType::Concurrent);
+        match self
+            .retry
+            .run(|| files::upload_session_start(&self.client, &arg, &[]))
+        {
+            Ok(Ok(result)) => Ok(result.session_id),
+            Ok(Err(e)) => Err(anyhow::anyhow!(format!("{}", e))),
+            Err(e) => Err(anyhow::anyhow!(format!("{}", e))),
+        }
+    }
+
+    fn upload_session_append(
+        &self,
+        session_id: &str,
+        offset: u64,
+        data: &[u8],
+        close: bool,
+    ) -> Result<()> {
+        let mut append = files::UploadSessionAppendArg::new(files::UploadSessionCursor::new(
+            session_id.to_string(),
+            offset,
+        ));
+        append.close = close;
+        match self.retry.run(|| {
+            let _permit = self
+                .limiter
+                .as_ref()
+                .map(|limiter| limiter.acquire(data.len() as u64));
+            files::upload_session_append_v2(&self.client, &append, data)
+        }) {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(
+                e @ files::UploadSessionLookupError::NotFound
+                | e @ files::UploadSessionLookupError::IncorrectOffset(_)
+                | e @ files::UploadSessionLookupError::Closed,
+            )) => Err(SessionLookupError {
+                session_id: session_id.to_string(),
+                message: format!("{}", e),
+            })?,
+            Ok(Err(e)) => Err(anyhow::anyhow!(format!("{}", e))),
+            Err(e) => Err(anyhow::anyhow!(format!("{}", e))),
+        }
+    }
+
+    fn finish_batch(&self, entries: &[FinishEntry]) -> Result<FinishBatchStatus> {
+        let finishes = entries
+            .iter()
+            .map(|entry| {
+                files::UploadSessionFinishArg::new(
+                    files::UploadSessionCursor::new(entry.session_id.clone(), entry.offset),
+                    if entry.overwrite {
+                        files::CommitInfo::new(entry.path.clone())
+                            .with_mode(files::WriteMode::Overwrite)
+                    } else {
+                        files::CommitInfo::new(entry.path.clone())
+                    },
+                )
+            })
+            .collect();
+        let arg = files::UploadSessionFinishBatchArg::new(finishes);
+        match self
+            .retry
+            .run(|| files::upload_session_finish_batch(&self.client, &arg))
+        {
+            Ok(Ok(files::UploadSessionFinishBatchLaunch::AsyncJobId(async_job_id))) => {
+                Ok(FinishBatchStatus::InProgress(async_job_id))
+            }
+            Ok(Ok(files::UploadSessionFinishBatchLaunch::Complete(result))) => {
+                Ok(Self::batch_result(result))
+            }
+            Ok(Ok(launch)) => Err(anyhow::anyhow!(format!(
+                "unexpected finish batch response: {:?}",
+                launch
+            ))),
+            Ok(Err(e)) => Err(anyhow::anyhow!(format!("{}", e))),
+            Err(e) => Err(anyhow::anyhow!(format!("{}", e))),
+        }
+    }
+
+    fn finish_batch_check(&self, async_job_id: &str) -> Result<FinishBatchStatus> {
+        let poll_arg = dbx_async::PollArg::new(async_job_id.to_string());
+        match self
+            .retry
+            .run(|| files::upload_session_finish_batch_check(&self.client, &poll_arg))
+        {
+            Ok(Ok(files::UploadSessionFinishBatchJobStatus::InProgress)) => {
+                Ok(FinishBatchStatus::InProgress(async_job_id.to_string()))
+            }
+            Ok(Ok(files::UploadSessionFinishBatchJobStatus::Complete(result))) => {
+                Ok(Self::batch_result(result))
+            }
+            Ok(Err(e)) => Err(anyhow::anyhow!(format!("{}", e))),
+            Err(e) => Err(anyhow::anyhow!(format!("{}", e))),
+        }
+    }
+
+    fn get_metadata(&self, path: &str) -> Result<Option<RemoteFile>> {
+        let arg = files::GetMetadataArg::new(path.to_string());
+        match self.retry.run(|| files::get_metadata(&self.client, &arg)) {
+            Ok(Ok(Metadata::File(file))) => Ok(Some(remote_file(file))),
+            Ok(Ok(_)) => Ok(None),
+            Ok(Err(files::GetMetadataError::Path(files::LookupError::NotFound))) => Ok(None),
+            Ok(Err(e)) => Err(anyhow::anyhow!(format!("{}", e))),
+            Err(e) => Err(anyhow::anyhow!(format!("{}", e))),
+        }
+    }
+
+    fn download(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let arg = files::DownloadArg::new(path.to_string());
+        let result = match self
+            .retry
+            .run(|| files::download(&self.client, &arg, None, None))
+        {
+            Ok(Ok(result)) => result,
+            Ok(Err(files::DownloadError::Path(files::LookupError::NotFound))) => return Ok(None),
+            Ok(Err(e)) => Err(anyhow::anyhow!(format!("{}", e)))?,
+            Err(e) => Err(anyhow::anyhow!(format!("{}", e)))?,
+        };
+        let mut data = Vec::new();
+        if let Some(mut body) = result.body {
+            body.read_to_end(&mut data)?;
+        }
+        Ok(Some(data))
+    }
 }
 
-pub fn upload_file(mut source_file: File, dest_path: String) -> Result<()> {
-    let client = Arc::new(UserAuthDefaultClient::new(get_oauth2_token()));
-    let source_len = source_file.metadata()?.len();
-    let session = UploadSession::new(&client, source_len)?;
-    let session_id = session.session_id.clone();
+#[derive(Debug, Clone, Error)]
+pub enum UploadError {
+    #[error("ReadError: {path}: {message}")]
+    ReadError { path: String, message: String },
+    #[error("SessionError: {path}: {message}")]
+    SessionError { path: String, message: String },
+    #[error("AppendError: {path}: block at offset {offset}: {message}")]
+    AppendError {
+        path: String,
+        offset: u64,
+        message: String,
+    },
+    /// The store no longer knows the session, so the file has to be uploaded again.
+    #[error("SessionLost: {path}: {message}")]
+    SessionLost {
+        path: String,
+        session_id: String,
+        message: String,
+    },
+    #[error("FinishError: {0}")]
+    FinishError(String),
+    #[error("EntryError: {path}: {message}")]
+    EntryError { path: String, message: String },
+    #[error("PollError: {0}")]
+    PollError(String),
+}
+
+/// Outcome of `upload_files`, by local path.
+#[derive(Debug, Default)]
+pub struct UploadReport {
+    pub uploaded: Vec<String>,
+    pub failed: Vec<(String, UploadError)>,
+}
+
+impl UploadReport {
+    fn merge(&mut self, mut other: Self) {
+        self.uploaded.append(&mut other.uploaded);
+        self.failed.append(&mut other.failed);
+    }
+}
+
+/// Appends every block of `source_file` that the session doesn't have yet.
+fn upload_blocks(
+    store: Arc<dyn RemoteStore>,
+    source_file: &mut File,
+    path: &str,
+    journal: Option<Arc<SessionJournal>>,
+) -> std::result::Result<Arc<UploadSession>, UploadError> {
+    let read_error = |e: &dyn std::fmt::Display| UploadError::ReadError {
+        path: path.to_string(),
+        message: format!("{}", e),
+    };
+    let stamp = source_file
+        .metadata()
+        .and_then(|metadata| FileStamp::from_metadata(&metadata))
+        .map_err(|e| read_error(&e))?;
+    let source_len = stamp.size as u64;
+    let session = UploadSession::open(
+        store.as_ref(),
+        stamp,
+        journal.map(|journal| (journal, path)),
+    )
+    .map_err(|e| UploadError::SessionError {
+        path: path.to_string(),
+        message: format!("{}", e),
+    })?;
+    let session = Arc::new(session);
     let start_offset = session.start_offset;
-    let cloned_client = client.clone();
     println!("upload session ID is {}", session.session_id);
+    if start_offset >= source_len {
+        return Ok(session);
+    }
+    source_file
+        .seek(SeekFrom::Start(start_offset))
+        .map_err(|e| read_error(&e))?;
+    let cloned = session.clone();
     let result = parallel_reader::read_stream_and_process_chunks_in_parallel(
-        &mut source_file,
+        source_file,
         BLOCK_SIZE,
         PARALLELISM,
         Arc::new(move |block_offset, data: &[u8]| -> Result<()> {
-            let mut append = files::UploadSessionAppendArg::new(files::UploadSessionCursor::new(
-                session_id.clone(),
-                start_offset + block_offset,
-            ));
-            if data.len() != BLOCK_SIZE {
-                append.close = true;
+            let offset = start_offset + block_offset;
+            if cloned.is_block_uploaded(offset) {
+                return Ok(());
             }
-            files::upload_session_append_v2(cloned_client.as_ref(), &append, data);
-            Ok(())
+            let len = data.len() as u64;
+            store.upload_session_append(
+                &cloned.session_id,
+                offset,
+                data,
+                offset + len == cloned.file_size,
+            )?;
+            cloned.mark_block_uploaded(offset, len)
         }),
     );
-    let finish = files::UploadSessionFinishArg::new(
-        files::UploadSessionCursor::new(session.session_id.clone(), source_len),
-        files::CommitInfo::new(dest_path),
-    );
-    files::upload_session_finish(client.as_ref(), &finish, &[]);
+    match result {
+        Ok(()) => Ok(session),
+        Err(parallel_reader::Error::Read(e)) => Err(read_error(&e)),
+        Err(parallel_reader::Error::Process { error, .. })
+            if error.downcast_ref::<SessionLookupError>().is_some() =>
+        {
+            Err(UploadError::SessionLost {
+                path: path.to_string(),
+                session_id: session.session_id.clone(),
+                message: format!("{}", error),
+            })
+        }
+        Err(parallel_reader::Error::Process {
+            chunk_offset,
+            error,
+        }) => Err(UploadError::AppendError {
+            path: path.to_string(),
+            offset: start_offset + chunk_offset,
+            message: format!("{}", error),
+        }),
+    }
+}
+
+/// Commits the entries and polls the batch job until it completes.
+fn finish_batch(
+    store: &dyn RemoteStore,
+    entries: &[FinishEntry],
+) -> std::result::Result<Vec<FinishEntryResult>, UploadError> {
+    let mut status = store
+        .finish_batch(entries)
+        .map_err(|e| UploadError::FinishError(format!("{}", e)))?;
+    loop {
+        match status {
+            FinishBatchStatus::InProgress(async_job_id) => {
+                println!("batch check inprogress");
+                thread::sleep(POLL_INTERVAL);
+                status = store
+                    .finish_batch_check(&async_job_id)
+                    .map_err(|e| UploadError::PollError(format!("{}", e)))?;
+            }
+            FinishBatchStatus::Complete(results) => {
+                println!("batch check complete");
+                return Ok(results);
+            }
+        }
+    }
+}
+
+/// Uploads `source_file` to `dest_path`, replacing whatever is there.
+pub fn upload_file(
+    store: Arc<dyn RemoteStore>,
+    mut source_file: File,
+    dest_path: String,
+) -> Result<()> {
+    let session = upload_blocks(store.clone(), &mut source_file, &dest_path, None)?;
+    let finish = FinishEntry {
+        session_id: session.session_id.clone(),
+        offset: session.file_size,
+        path: dest_path.clone(),
+        overwrite: true,
+    };
+    for result in finish_batch(store.as_ref(), &[finish])? {
+        if let Err(message) = result {
+            Err(UploadError::EntryError {
+                path: dest_path.clone(),
+                message,
+            })?;
+        }
+    }
     Ok(())
 }
 
-pub async fn upload_files(files: DatetimeExtnameDigests) -> Result<()> {
+/// The (local path, remote path) pairs to upload and the local paths already uploaded.
+type NamedFiles = (Vec<(String, String)>, Vec<String>);
+
+/// Names the files taken at `datetime` that aren't in the index yet.
+fn name_files(
+    conn: &Connection,
+    config: &Config,
+    datetime: &str,
+    datetime_files: &SumNameDigests,
+) -> Result<NamedFiles> {
+    let mut new = Vec::new();
+    let mut duplicates = Vec::new();
+    // Names only collide within an extension, so each one counts on its own.
+    let mut used: HashMap<&str, BTreeSet<u32>> = HashMap::new();
+    // Counters of the JPEGs etc., so a RAW taken with one can get the same name.
+    let mut sibling_counts: HashMap<String, u32> = HashMap::new();
+    // RAWs go last so their siblings are counted first.
+    let files = datetime_files
+        .pic
+        .iter()
+        .filter(|file| !file.extension.is_raw())
+        .chain(
+            datetime_files
+                .pic
+                .iter()
+                .filter(|file| file.extension.is_raw()),
+        )
+        .chain(datetime_files.mov.iter());
+    for file in files {
+        if exist(conn, file.digest.clone())? {
+            duplicates.push(file.path.clone());
+            continue;
+        }
+        let ext = file.extension.remote_extension();
+        let used = used.entry(ext).or_default();
+        let stem = file_stem(&file.name);
+        let count = match (file.extension.is_raw(), sibling_counts.get(&stem)) {
+            (true, Some(count)) if !used.contains(count) => *count,
+            // A lone RAW keeps clear of names that look like another file's pair.
+            (true, _) => (0..)
+                .find(|count| !used.contains(count) && !sibling_counts.values().any(|c| c == count))
+                .unwrap(),
+            (false, _) => (0..).find(|count| !used.contains(count)).unwrap(),
+        };
+        used.insert(count);
+        if !file.extension.is_raw() && file.extension.is_picture() {
+            sibling_counts.entry(stem).or_insert(count);
+        }
+        new.push((
+            file.path.clone(),
+            config.remote_path(datetime, count, file, ext)?,
+        ));
+    }
+    Ok((new, duplicates))
+}
+
+/// Case-insensitive, as cameras name both halves of a RAW+JPEG pair alike.
+fn file_stem(name: &str) -> String {
+    Path::new(name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(name)
+        .to_lowercase()
+}
+
+/// What `upload_files` would do with the scanned files, without touching the remote side.
+#[derive(Debug, Default)]
+pub struct UploadPlan {
+    /// (local path, remote path)
+    pub new: Vec<(String, String)>,
+    /// Local paths whose content is already in the index.
+    pub duplicates: Vec<String>,
+}
+
+pub fn plan_upload(
+    db_path: &str,
+    files: &DatetimeExtnameDigests,
+    config: &Config,
+) -> Result<UploadPlan> {
+    plan_upload_with(&connection(db_path)?, files, config)
+}
+
+/// `plan_upload` against an index that is already open.
+pub fn plan_upload_with(
+    conn: &Connection,
+    files: &DatetimeExtnameDigests,
+    config: &Config,
+) -> Result<UploadPlan> {
+    let mut plan = UploadPlan::default();
+    let mut datetimes: Vec<&String> = files.keys().collect();
+    datetimes.sort();
+    for datetime in datetimes {
+        let (mut new, mut duplicates) = name_files(conn, config, datetime, &files[datetime])?;
+        plan.new.append(&mut new);
+        plan.duplicates.append(&mut duplicates);
+    }
+    Ok(plan)
+}
+
+pub async fn upload_files(
+    store: Arc<dyn RemoteStore>,
+    db_path: &str,
+    files: DatetimeExtnameDigests,
+    config: &Config,
+) -> Result<UploadReport> {
     println!("upload start");
-    let client = Arc::new(UserAuthDefaultClient::new(get_oauth2_token()));
     let max = 1000;
     let mut sum = 0;
     let mut threads = Vec::new();
     let mut path_names = Vec::new();
-    let conn = connection("my-dropbox.db3")?;
+    let conn = connection(db_path)?;
+    let journal = Arc::new(SessionJournal::open(db_path)?);
 
     for (datetime, datetime_files) in files {
         println!("1, len: {}", path_names.len());
-        sum = sum + datetime_files.sum;
+        sum += datetime_files.sum;
         if sum <= max {
             println!("2");
-            let mut count = 0;
-            for pic in datetime_files.pic {
-                if exist(&conn, pic.digest)? {
-                    continue;
-                }
-                let name = if count != 0 {
-                    format!("{}_{}.jpg", datetime, count)
-                } else {
-                    format!("{}.jpg", datetime)
-                };
-                path_names.push((pic.path, name));
-                count = count + 1;
-            }
-            let mut count = 0;
-            for mov in datetime_files.mov {
-                if exist(&conn, mov.digest)? {
-                    continue;
-                }
-                let name = if count != 0 {
-                    format!("{}_{}.mp4", datetime, count)
-                } else {
-                    format!("{}.mp4", datetime)
-                };
-                path_names.push((mov.path, name));
-                count = count + 1;
-            }
         } else {
             println!("3");
             let cloned = path_names.clone();
-            let cloned_client = client.clone();
+            let cloned_store = store.clone();
+            let cloned_journal = journal.clone();
+            let cloned_db_path = db_path.to_string();
             threads.push(tokio::spawn(async move {
-                upload_files2(cloned, cloned_client).await
+                upload_files2(cloned, cloned_store, cloned_journal, &cloned_db_path).await
             }));
             path_names.clear();
-            let mut count = 0;
-            for pic in datetime_files.pic {
-                if exist(&conn, pic.digest)? {
-                    continue;
-                }
-                let name = if count != 0 {
-                    format!("{}_{}.jpg", datetime, count)
-                } else {
-                    format!("{}.jpg", datetime)
-                };
-                path_names.push((pic.path, name));
-                count = count + 1;
-            }
-            let mut count = 0;
-            for mov in datetime_files.mov {
-                if exist(&conn, mov.digest)? {
-                    continue;
-                }
-                let name = if count != 0 {
-                    format!("{}_{}.mp4", datetime, count)
-                } else {
-                    format!("{}.mp4", datetime)
-                };
-                path_names.push((mov.path, name));
-                count = count + 1;
-            }
             sum = datetime_files.sum;
         }
+        let (new, _) = name_files(&conn, config, &datetime, &datetime_files)?;
+        for (path, dest_path) in new {
+            let file_type = if datetime_files.pic.iter().any(|file| file.path == path) {
+                FileType::Picture
+            } else {
+                FileType::Movie
+            };
+            path_names.push(PendingUpload {
+                path,
+                dest_path,
+                file_type,
+                capture_time: datetime.clone(),
+            });
+        }
     }
+    let db_path = db_path.to_string();
     threads.push(tokio::spawn(async move {
-        upload_files2(path_names, client).await
+        upload_files2(path_names, store, journal, &db_path).await
     }));
     println!("4");
     let finishes = futures::future::join_all(threads).await;
     println!("5");
-    Ok(())
+    let mut report = UploadReport::default();
+    for finish in finishes {
+        report.merge(finish??);
+    }
+    Ok(report)
 }
-enum UploadMessage {
-    Cont((String, SumNameDigests)),
-    Finish(u32),
+
+/// A file to upload, with what the index records about it besides the remote metadata.
+#[derive(Debug, Clone)]
+struct PendingUpload {
+    path: String,
+    dest_path: String,
+    file_type: FileType,
+    capture_time: String,
 }
 
 async fn upload_files2(
-    path_names: Vec<(String, String)>,
-    client: Arc<UserAuthDefaultClient>,
-) -> Result<()> {
+    path_names: Vec<PendingUpload>,
+    store: Arc<dyn RemoteStore>,
+    journal: Arc<SessionJournal>,
+    db_path: &str,
+) -> Result<UploadReport> {
     let start_time: DateTime<Local> = Local::now();
     println!(
         "upload thread start: {}, len: {}",
         start_time,
         path_names.len()
     );
+    let mut report = UploadReport::default();
+    let mut paths = Vec::new();
     let mut threads = Vec::new();
-    for (path, name) in path_names {
-        let cloned = client.clone();
+    for upload in path_names {
+        let cloned = store.clone();
+        let cloned_journal = journal.clone();
         println!("thread spawn");
-        threads.push(tokio::spawn(
-            async move { upload_file2(&path, &name, cloned) },
-        ));
+        let path = upload.path.clone();
+        let dest_path = upload.dest_path.clone();
+        paths.push(upload);
+        threads.push(tokio::spawn(async move {
+            upload_file2(&path, &dest_path, cloned, cloned_journal)
+        }));
     }
     let finishes = futures::future::join_all(threads).await;
-    let mut v: Vec<files::UploadSessionFinishArg> = Vec::new();
-    for finish in finishes {
+    let mut v: Vec<FinishEntry> = Vec::new();
+    let mut v_paths = Vec::new();
+    for (upload, finish) in paths.into_iter().zip(finishes) {
         match finish {
-            Ok(Ok(f)) => v.push(f),
+            Ok(Ok(f)) => {
+                v.push(f);
+                v_paths.push(upload);
+            }
             Ok(Err(e)) => {
-                println!("OK Error: {}", e)
+                report.failed.push((upload.path, e));
             }
             Err(e) => {
-                println!("Error: {}", e)
+                report.failed.push((
+                    upload.path.clone(),
+                    UploadError::ReadError {
+                        path: upload.path,
+                        message: format!("{}", e),
+                    },
+                ));
             }
         }
     }
-    let finish_batch_arg = files::UploadSessionFinishBatchArg::new(v);
-    match files::upload_session_finish_batch(client.as_ref(), &finish_batch_arg) {
-        Ok(Ok(res)) => match res {
-            files::UploadSessionFinishBatchLaunch::AsyncJobId(async_job_id) => {
-                let poll_arg = dbx_async::PollArg::new(async_job_id);
-                loop {
-                    match files::upload_session_finish_batch_check(client.as_ref(), &poll_arg) {
-                        Ok(Ok(res)) => match res {
-                            files::UploadSessionFinishBatchJobStatus::InProgress => {
-                                println!("batch check inprogress");
-                            }
-                            files::UploadSessionFinishBatchJobStatus::Complete(_) => {
-                                println!("batch check complete");
-                                break;
-                            }
-                        },
-                        Ok(Err(e)) => {
-                            println!("batch check ok err: {}", e);
-                            break;
-                        }
-                        Err(e) => {
-                            println!("batch check err: {}", e);
-                            break;
-                        }
+    if v.is_empty() {
+        return Ok(report);
+    }
+    match finish_batch(store.as_ref(), &v) {
+        Ok(results) => {
+            println!("upload batch finish");
+            let conn = connection(db_path)?;
+            for ((finish, upload), result) in v.iter().zip(v_paths).zip(results) {
+                // The session is closed now whether or not its commit succeeded.
+                journal.remove(&finish.session_id)?;
+                match result {
+                    Ok(file) => {
+                        record_upload(&conn, &file, upload.file_type, &upload.capture_time)?;
+                        report.uploaded.push(upload.path);
+                    }
+                    Err(message) => {
+                        report.failed.push((
+                            upload.path,
+                            UploadError::EntryError {
+                                path: finish.path.clone(),
+                                message,
+                            },
+                        ));
                     }
                 }
             }
-            files::UploadSessionFinishBatchLaunch::Complete(_) => {
-                println!("upload batch finish");
+        }
+        Err(e) => {
+            for upload in v_paths {
+                report.failed.push((upload.path, e.clone()));
             }
-            _ => {}
-        },
-        Ok(Err(e)) => println!("Finish batch Ok Err : {}", e),
-        Err(e) => println!("Finish batch Err : {}", e),
+        }
     }
     let end_time: DateTime<Local> = Local::now();
     println!(
@@ -469,40 +906,46 @@ async fn upload_files2(
         start_time,
         (end_time - start_time).num_seconds()
     );
-    Ok(())
+    Ok(report)
 }
 
 pub fn upload_file2(
-    path: &String,
-    name: &String,
-    client: Arc<UserAuthDefaultClient>,
-) -> Result<files::UploadSessionFinishArg> {
-    let mut source_file = File::open(Path::new(&path))?;
-    let source_len = source_file.metadata()?.len();
-    let mut session = UploadSession::new(&client, source_len)?;
-    let session_id = session.session_id.clone();
-    let start_offset = session.start_offset;
-    // let cloned_client = client.clone();
-    println!("-------upload session ID is {}", session.session_id);
-    let result = parallel_reader::read_stream_and_process_chunks_in_parallel(
-        &mut source_file,
-        BLOCK_SIZE,
-        PARALLELISM,
-        Arc::new(move |block_offset, data: &[u8]| -> Result<()> {
-            let mut append = files::UploadSessionAppendArg::new(files::UploadSessionCursor::new(
-                session_id.clone(),
-                start_offset + block_offset,
-            ));
-            if data.len() != BLOCK_SIZE {
-                append.close = true;
-            }
-            files::upload_session_append_v2(client.as_ref(), &append, data);
-            Ok(())
-        }),
-    );
-    let finish = files::UploadSessionFinishArg::new(
-        files::UploadSessionCursor::new(session.session_id.clone(), source_len),
-        files::CommitInfo::new(format!("/カメラアップロード/{}", name)),
-    );
-    Ok(finish)
+    path: &str,
+    dest_path: &str,
+    store: Arc<dyn RemoteStore>,
+    journal: Arc<SessionJournal>,
+) -> std::result::Result<FinishEntry, UploadError> {
+    let mut source_file = File::open(Path::new(&path)).map_err(|e| UploadError::ReadError {
+        path: path.to_string(),
+        message: format!("{}", e),
+    })?;
+    let session = match upload_blocks(store.clone(), &mut source_file, path, Some(journal.clone()))
+    {
+        // A session journaled by an earlier run can expire before it is resumed; without
+        // dropping it every later run would pick it up and fail again.
+        Err(UploadError::SessionLost {
+            session_id,
+            message,
+            ..
+        }) => {
+            eprintln!(
+                "upload session {} lost, starting over: {}",
+                session_id, message
+            );
+            journal
+                .remove(&session_id)
+                .map_err(|e| UploadError::SessionError {
+                    path: path.to_string(),
+                    message: format!("{}", e),
+                })?;
+            upload_blocks(store, &mut source_file, path, Some(journal))?
+        }
+        result => result?,
+    };
+    Ok(FinishEntry {
+        session_id: session.session_id.clone(),
+        offset: session.file_size,
+        path: dest_path.to_string(),
+        overwrite: false,
+    })
 }
@@ -1,47 +1,59 @@
 use anyhow::{Context, Result};
-use std::fmt;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 use std::str::FromStr;
-#[derive(PartialEq, Hash, Eq, Debug)]
+#[derive(PartialEq, Hash, Eq, Debug, Clone, Copy)]
 pub enum Extension {
     Jpeg,
+    /// HEIC/HEIF stills, uploaded like JPEGs.
+    Heif,
+    // RAW stills. All are TIFF based except CR3, which is ISO-BMFF.
+    Dng,
+    Cr2,
+    Cr3,
+    Nef,
+    Arw,
+    Orf,
+    Rw2,
+    Pef,
     Mp4,
+    /// QuickTime, read like MP4.
+    Mov,
+    M4v,
+    ThreeGp,
+    Avi,
+    // Recognized by content so a misnamed file is reported, but not uploaded.
+    Tiff,
+    Png,
+    Gif,
+    Webp,
     Other,
 }
 
-#[derive(Debug)]
-enum ExtensionError {
-    UnknownExtensionError(String),
-}
-
-impl fmt::Display for ExtensionError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use self::ExtensionError::*;
-        match self {
-            UnknownExtensionError(s) => write!(f, "UnknownExtensionError: {}", s),
-        }
-    }
-}
-
-impl std::error::Error for ExtensionError {}
-
-// impl FromStr for Extension {
-//     type Err = anyhow::Error;
-//     fn from_str(s: &str) -> Result<Self, Self::Err> {
-//         match s {
-//             "jpeg" | "JPEG" | "jpg" | "JPG" => Ok(Extension::Jpeg),
-//             "mp4" | "MP4" => Ok(Extension::Mp4),
-//             extension => Err(ExtensionError::UnknownExtensionError(extension.to_string()))?,
-//         }
-//     }
-// }
-
 impl FromStr for Extension {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "jpeg" | "JPEG" | "jpg" | "JPG" => Ok(Extension::Jpeg),
-            "mp4" | "MP4" => Ok(Extension::Mp4),
+        match s.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(Extension::Jpeg),
+            "heic" | "heif" => Ok(Extension::Heif),
+            "dng" => Ok(Extension::Dng),
+            "cr2" => Ok(Extension::Cr2),
+            "cr3" => Ok(Extension::Cr3),
+            "nef" => Ok(Extension::Nef),
+            "arw" => Ok(Extension::Arw),
+            "orf" => Ok(Extension::Orf),
+            "rw2" => Ok(Extension::Rw2),
+            "pef" => Ok(Extension::Pef),
+            "mp4" => Ok(Extension::Mp4),
+            "mov" => Ok(Extension::Mov),
+            "m4v" => Ok(Extension::M4v),
+            "3gp" => Ok(Extension::ThreeGp),
+            "avi" => Ok(Extension::Avi),
+            "tif" | "tiff" => Ok(Extension::Tiff),
+            "png" => Ok(Extension::Png),
+            "gif" => Ok(Extension::Gif),
+            "webp" => Ok(Extension::Webp),
             _ => Ok(Extension::Other),
         }
     }
@@ -55,4 +67,115 @@ impl Extension {
             .and_then(|ext| ext.to_str().ok_or(anyhow::anyhow!("")))?;
         Extension::from_str(ex)
     }
+
+    /// The type of the file at `path` by its content. `claimed`, the type its extension says,
+    /// only decides between formats sharing a header and stands for content we don't know.
+    pub fn detect(path: &Path, claimed: Extension) -> Result<Self> {
+        let mut head = Vec::with_capacity(16);
+        File::open(path)
+            .with_context(|| format!("failed to open file: {:?}", path))?
+            .take(16)
+            .read_to_end(&mut head)?;
+        Ok(Extension::from_magic(&head, claimed).unwrap_or(claimed))
+    }
+
+    /// Identifies the first 16 bytes of a file.
+    pub fn from_magic(head: &[u8], claimed: Extension) -> Option<Self> {
+        let detected = match head {
+            [0xff, 0xd8, 0xff, ..] => Extension::Jpeg,
+            [0x89, b'P', b'N', b'G', ..] => Extension::Png,
+            [b'G', b'I', b'F', b'8', ..] => Extension::Gif,
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Extension::Webp,
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'A', b'V', b'I', b' ', ..] => Extension::Avi,
+            [b'I', b'I', b'R', b'O', ..]
+            | [b'I', b'I', b'R', b'S', ..]
+            | [b'M', b'M', b'O', b'R', ..] => Extension::Orf,
+            [b'I', b'I', b'U', 0, ..] => Extension::Rw2,
+            [b'I', b'I', b'*', 0, _, _, _, _, b'C', b'R', 2, ..] => Extension::Cr2,
+            // DNG, NEF, ARW and PEF all start as plain TIFF.
+            [b'I', b'I', b'*', 0, ..] | [b'M', b'M', 0, b'*', ..] => match claimed {
+                Extension::Dng | Extension::Nef | Extension::Arw | Extension::Pef => claimed,
+                _ => Extension::Tiff,
+            },
+            [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] if brand.len() >= 4 => {
+                match &brand[..4] {
+                    b"crx " => Extension::Cr3,
+                    b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1"
+                    | b"msf1" => Extension::Heif,
+                    b"qt  " => Extension::Mov,
+                    b"M4V " | b"M4VH" | b"M4VP" => Extension::M4v,
+                    [b'3', b'g', ..] => Extension::ThreeGp,
+                    _ => Extension::Mp4,
+                }
+            }
+            // QuickTime files older than `ftyp`.
+            [_, _, _, _, b'm', b'o', b'o', b'v', ..]
+            | [_, _, _, _, b'm', b'd', b'a', b't', ..]
+            | [_, _, _, _, b'w', b'i', b'd', b'e', ..] => Extension::Mov,
+            _ => return None,
+        };
+        // Brands are used loosely across the MP4 family, and all of it is read the same way.
+        if detected.is_iso_movie() && claimed.is_iso_movie() {
+            return Some(claimed);
+        }
+        Some(detected)
+    }
+
+    /// Extension of the uploaded file's name. `Other` files are never uploaded.
+    pub fn remote_extension(&self) -> &'static str {
+        match self {
+            Extension::Jpeg => "jpg",
+            Extension::Heif => "heic",
+            Extension::Dng => "dng",
+            Extension::Cr2 => "cr2",
+            Extension::Cr3 => "cr3",
+            Extension::Nef => "nef",
+            Extension::Arw => "arw",
+            Extension::Orf => "orf",
+            Extension::Rw2 => "rw2",
+            Extension::Pef => "pef",
+            Extension::Mp4 => "mp4",
+            Extension::Mov => "mov",
+            Extension::M4v => "m4v",
+            Extension::ThreeGp => "3gp",
+            Extension::Avi => "avi",
+            Extension::Tiff => "tif",
+            Extension::Png => "png",
+            Extension::Gif => "gif",
+            Extension::Webp => "webp",
+            Extension::Other => "",
+        }
+    }
+
+    /// Goes into `SumNameDigests::pic`.
+    pub fn is_picture(&self) -> bool {
+        matches!(self, Extension::Jpeg | Extension::Heif) || self.is_raw()
+    }
+
+    pub fn is_raw(&self) -> bool {
+        matches!(
+            self,
+            Extension::Dng
+                | Extension::Cr2
+                | Extension::Cr3
+                | Extension::Nef
+                | Extension::Arw
+                | Extension::Orf
+                | Extension::Rw2
+                | Extension::Pef
+        )
+    }
+
+    /// Goes into `SumNameDigests::mov`.
+    pub fn is_movie(&self) -> bool {
+        self.is_iso_movie() || *self == Extension::Avi
+    }
+
+    /// The MP4 family, read by `get_mp4_datetime`.
+    pub fn is_iso_movie(&self) -> bool {
+        matches!(
+            self,
+            Extension::Mp4 | Extension::Mov | Extension::M4v | Extension::ThreeGp
+        )
+    }
 }
@@ -0,0 +1,157 @@
+use crate::calc::{add_scanned, sort_calc, Scan, Stage};
+use crate::config::{Config, DATETIME_FORMAT};
+use crate::digest::digests;
+use crate::dropbox::plan_upload_with;
+use crate::extension::Extension;
+use crate::meta::{datetime, details, Details};
+use crate::sqlite::{read_only_connection, CachedScan};
+use anyhow::{Context, Result};
+use chrono_tz::Tz;
+use data_encoding::HEXLOWER;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// What `meta` shows for one file.
+#[derive(Debug, Default, Serialize)]
+pub struct FileInfo {
+    pub path: String,
+    /// The type detected from the content.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// RFC 3339, in the offset it was taken at.
+    pub capture_time: Option<String>,
+    pub date_source: Option<String>,
+    /// The zone assumed for capture times that don't record an offset.
+    pub timezone: String,
+    #[serde(flatten)]
+    pub details: Details,
+    pub sha256: Option<String>,
+    pub content_hash: Option<String>,
+    /// The path `upload` would give the file, if it would upload it.
+    pub remote_name: Option<String>,
+    /// Already in the index, so `upload` skips it.
+    pub duplicate: bool,
+    pub error: Option<String>,
+}
+
+/// Metadata of the files at `paths`, recursing into directories, named as `upload` would
+/// name them together against the index at `db_path`.
+pub fn inspect(paths: &[PathBuf], db_path: &str, config: &Config) -> Result<Vec<FileInfo>> {
+    let zone = config.zone()?;
+    let mut files = Vec::new();
+    for path in paths {
+        collect_files(path, &mut files)?;
+    }
+    let mut scan = Scan::default();
+    let mut infos = Vec::new();
+    for file in &files {
+        let (mut info, scanned) = file_info(file, zone, config);
+        if let Some(scanned) = scanned {
+            if let Err(failure) = add_scanned(&mut scan, file, scanned) {
+                info.error = Some(format!("{}: {}", failure.stage, failure.error));
+            }
+        }
+        infos.push(info);
+    }
+    sort_calc(&mut scan.files);
+    // `meta` only reads, so it neither creates nor upgrades the index.
+    let plan = plan_upload_with(&read_only_connection(db_path)?, &scan.files, config)?;
+    let remote_names: HashMap<&str, &str> = plan
+        .new
+        .iter()
+        .map(|(path, remote_path)| (path.as_str(), remote_path.as_str()))
+        .collect();
+    let duplicates: HashSet<&str> = plan.duplicates.iter().map(|path| path.as_str()).collect();
+    for info in &mut infos {
+        info.remote_name = remote_names
+            .get(info.path.as_str())
+            .map(|name| name.to_string());
+        info.duplicate = duplicates.contains(info.path.as_str());
+        if info.error.is_none() && info.remote_name.is_none() && !info.duplicate {
+            info.error = Some("not uploaded: unsupported type".to_string());
+        }
+    }
+    Ok(infos)
+}
+
+/// Reads the file once, for what `meta` shows and for what `upload` would plan from, if it
+/// would upload the file at all.
+fn file_info(path: &Path, zone: Tz, config: &Config) -> (FileInfo, Option<CachedScan>) {
+    let failed = |stage: Stage, e: anyhow::Error| Some(format!("{}: {:#}", stage, e));
+    let claimed = Extension::from_path(path).unwrap_or(Extension::Other);
+    let mut info = FileInfo {
+        path: path.display().to_string(),
+        kind: format!("{:?}", claimed),
+        timezone: config.timezone.clone(),
+        ..FileInfo::default()
+    };
+    let (ext, file) = match Extension::detect(path, claimed).and_then(|ext| {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open file: {:?}", path.to_str()))?;
+        Ok((ext, file))
+    }) {
+        Ok(opened) => opened,
+        Err(e) => {
+            info.error = failed(Stage::Open, e);
+            return (info, None);
+        }
+    };
+    info.kind = format!("{:?}", ext);
+    let mut buff = BufReader::new(&file);
+    let uploaded = ext.is_picture() || ext.is_movie();
+    let capture_time = if uploaded {
+        match datetime(path, &mut buff, &ext, zone) {
+            Ok(capture_time) => {
+                info.capture_time = Some(capture_time.datetime.to_rfc3339());
+                info.date_source = Some(capture_time.source.to_string());
+                Some(capture_time)
+            }
+            Err(e) => {
+                info.error = failed(Stage::Metadata, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    info.details = details(&mut buff, &ext);
+    let digests = match digests(&mut buff) {
+        Ok(digests) => digests,
+        Err(e) => {
+            info.error = info.error.take().or_else(|| failed(Stage::Digest, e));
+            return (info, None);
+        }
+    };
+    info.sha256 = Some(HEXLOWER.encode(digests.sha256.as_ref()));
+    info.content_hash = Some(digests.content_hash.clone());
+    let scanned = capture_time.map(|capture_time| CachedScan {
+        datetime: capture_time.datetime.format(DATETIME_FORMAT).to_string(),
+        digest: digests.content_hash,
+        model: match ext {
+            ext if ext.is_picture() => info.details.model.clone(),
+            _ => None,
+        },
+        date_source: capture_time.source,
+        extension: ext,
+    });
+    (info, scanned)
+}
+
+/// `path` itself if it is a file, else every file under it, in name order.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        collect_files(&entry, files)?;
+    }
+    Ok(())
+}
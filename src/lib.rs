@@ -1,6 +1,15 @@
+pub mod auth;
+pub mod bmff;
 pub mod calc;
+pub mod client;
+pub mod config;
 pub mod digest;
 pub mod dropbox;
 pub mod extension;
+pub mod inspect;
+pub mod limit;
 pub mod meta;
+pub mod retry;
+pub mod riff;
 pub mod sqlite;
+pub mod store;
@@ -0,0 +1,141 @@
+use crate::store::{FinishBatchStatus, FinishEntry, ListPage, RemoteFile, RemoteStore};
+use anyhow::Result;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Caps in-flight block uploads and upload bandwidth across every file being uploaded.
+pub struct UploadLimiter {
+    max_in_flight: Option<usize>,
+    bytes_per_sec: Option<u64>,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+    /// When the bandwidth budget allows the next block to start.
+    next_start: Mutex<Instant>,
+}
+
+/// A claimed in-flight slot, given back on drop.
+pub struct Permit<'a> {
+    limiter: &'a UploadLimiter,
+}
+
+impl UploadLimiter {
+    /// `None` leaves that dimension unlimited.
+    pub fn new(max_in_flight: Option<usize>, bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            max_in_flight: max_in_flight.map(|max| max.max(1)),
+            bytes_per_sec: bytes_per_sec.filter(|rate| *rate > 0),
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks until a slot is free and `bytes` fit into the bandwidth budget.
+    pub fn acquire(&self, bytes: u64) -> Permit<'_> {
+        if let Some(max) = self.max_in_flight {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            while *in_flight >= max {
+                in_flight = self.released.wait(in_flight).unwrap();
+            }
+            *in_flight += 1;
+        }
+        let permit = Permit { limiter: self };
+        if let Some(rate) = self.bytes_per_sec {
+            // Reserve a time slot proportional to the block size, then wait for it.
+            let start = {
+                let mut next_start = self.next_start.lock().unwrap();
+                let start = (*next_start).max(Instant::now());
+                *next_start = start + Duration::from_secs_f64(bytes as f64 / rate as f64);
+                start
+            };
+            let now = Instant::now();
+            if start > now {
+                thread::sleep(start - now);
+            }
+        }
+        permit
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.limiter.max_in_flight.is_some() {
+            let mut in_flight = self.limiter.in_flight.lock().unwrap();
+            *in_flight -= 1;
+            self.limiter.released.notify_one();
+        }
+    }
+}
+
+/// Parses a byte count with an optional K/M/G suffix (powers of 1024), e.g. `512K`.
+pub fn parse_bytes(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (number, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c.to_ascii_uppercase()),
+        _ => (s, 'B'),
+    };
+    let multiplier = match unit {
+        'B' => 1,
+        'K' => 1024,
+        'M' => 1024 * 1024,
+        'G' => 1024 * 1024 * 1024,
+        _ => return Err(anyhow::anyhow!("unknown unit in {:?}", s)),
+    };
+    Ok(number.trim().parse::<u64>()? * multiplier)
+}
+
+/// Runs every block append of the wrapped store through a shared `UploadLimiter`. Only for
+/// stores that don't retry: `DropboxStore::with_limiter` paces each retried attempt as well.
+pub struct LimitedStore {
+    inner: Arc<dyn RemoteStore>,
+    limiter: Arc<UploadLimiter>,
+}
+
+impl LimitedStore {
+    pub fn new(inner: Arc<dyn RemoteStore>, limiter: Arc<UploadLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl RemoteStore for LimitedStore {
+    fn list_folder(&self, path: &str, recursive: bool) -> Result<ListPage> {
+        self.inner.list_folder(path, recursive)
+    }
+
+    fn list_folder_continue(&self, cursor: &str) -> Result<ListPage> {
+        self.inner.list_folder_continue(cursor)
+    }
+
+    fn upload_session_start(&self) -> Result<String> {
+        self.inner.upload_session_start()
+    }
+
+    fn upload_session_append(
+        &self,
+        session_id: &str,
+        offset: u64,
+        data: &[u8],
+        close: bool,
+    ) -> Result<()> {
+        let _permit = self.limiter.acquire(data.len() as u64);
+        self.inner
+            .upload_session_append(session_id, offset, data, close)
+    }
+
+    fn finish_batch(&self, entries: &[FinishEntry]) -> Result<FinishBatchStatus> {
+        self.inner.finish_batch(entries)
+    }
+
+    fn finish_batch_check(&self, async_job_id: &str) -> Result<FinishBatchStatus> {
+        self.inner.finish_batch_check(async_job_id)
+    }
+
+    fn get_metadata(&self, path: &str) -> Result<Option<RemoteFile>> {
+        self.inner.get_metadata(path)
+    }
+
+    fn download(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.download(path)
+    }
+}
@@ -1,25 +1,58 @@
-use anyhow::{Context, Result};
-use data_encoding::HEXUPPER;
-use my_dropbox_controller::calc::{calc, calc_starter, runner, sort_calc, sum_calc};
-use my_dropbox_controller::digest::{dpx_digest, sha_256_digest};
-use my_dropbox_controller::dropbox::{
-    get_file_metadata, list_directory, upload_file, upload_files,
+use anyhow::Result;
+use my_dropbox_controller::auth::{credentials_path, Authenticator, StaticToken, TokenSource};
+use my_dropbox_controller::calc::{runner, sort_calc, sum_calc, FileFailure, Scan};
+use my_dropbox_controller::config::{config_path, Config};
+use my_dropbox_controller::dropbox::{plan_upload, upload_files, DropboxStore};
+use my_dropbox_controller::inspect::inspect;
+use my_dropbox_controller::limit::{parse_bytes, LimitedStore, UploadLimiter};
+use my_dropbox_controller::retry::RetryPolicy;
+use my_dropbox_controller::sqlite::{
+    pull_index, push_index, reset_db as sqlite_reset_db, sync_db, ScanCache,
 };
-use my_dropbox_controller::extension::Extension;
-use my_dropbox_controller::meta::{get_datetime, get_mp4_datetime};
-use my_dropbox_controller::sqlite::reset_db as sqlite_reset_db;
-use std::collections::HashMap;
-use std::fmt;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
-use std::path::Path;
-use std::str::FromStr;
+use my_dropbox_controller::store::{LocalStore, RemoteStore};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use structopt::StructOpt;
-use thiserror::Error;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 #[derive(StructOpt)]
 struct Cli {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "use a local directory instead of Dropbox"
+    )]
+    local_store: Option<std::path::PathBuf>,
+    #[structopt(
+        long,
+        default_value = "5",
+        help = "how many times to try a Dropbox request that failed transiently"
+    )]
+    max_attempts: u32,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "config file [default: ~/.config/my-dropbox-controller/config.toml]"
+    )]
+    config: Option<std::path::PathBuf>,
+    #[structopt(long, help = "remote folder to upload into")]
+    dest_root: Option<String>,
+    #[structopt(
+        long,
+        help = "file name template: strftime format plus {counter}, {name} and {model}"
+    )]
+    name_template: Option<String>,
+    #[structopt(long, help = "strftime format of date subfolders, e.g. %Y/%m")]
+    subfolders: Option<String>,
+    #[structopt(long, help = "local index file")]
+    index: Option<String>,
+    #[structopt(long, help = "remote path the index is pushed to and pulled from")]
+    remote_index: Option<String>,
+    #[structopt(
+        long,
+        help = "timezone of capture times without a recorded offset, e.g. Europe/Paris"
+    )]
+    timezone: Option<String>,
     #[structopt(subcommand)]
     sub: Sub,
 }
@@ -32,158 +65,428 @@ enum Sub {
     Upload {
         #[structopt(parse(from_os_str))]
         path: std::path::PathBuf,
+        #[structopt(long, help = "maximum number of blocks being uploaded at once")]
+        max_in_flight: Option<usize>,
+        #[structopt(
+            long,
+            parse(try_from_str = parse_bytes),
+            help = "maximum upload bytes per second, e.g. 512K or 2M"
+        )]
+        bandwidth: Option<u64>,
+        #[structopt(long, help = "print what would be uploaded without uploading")]
+        dry_run: bool,
+        #[structopt(long, help = "abort on the first file that can't be read")]
+        strict: bool,
+        #[structopt(long, help = "read every file again instead of using the scan cache")]
+        rehash: bool,
     },
-    #[structopt(name = "meta", about = "get metadata of file")]
+    #[structopt(
+        name = "meta",
+        about = "show metadata and planned remote names of files and directories"
+    )]
     Meta {
-        #[structopt(parse(from_os_str))]
-        path: std::path::PathBuf,
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+        #[structopt(long, default_value = "table", possible_values = &["table", "json"])]
+        format: String,
     },
-    #[structopt(name = "test", about = "test")]
-    Test {
-        #[structopt(parse(from_os_str))]
-        path: std::path::PathBuf,
+    #[structopt(name = "auth", about = "manage Dropbox authorization")]
+    Auth(AuthSub),
+    #[structopt(name = "db", about = "sync the index with Dropbox")]
+    Db(DbSub),
+}
+
+#[derive(StructOpt)]
+enum DbSub {
+    #[structopt(
+        name = "pull",
+        about = "replace the local index with the one in Dropbox"
+    )]
+    Pull,
+    #[structopt(name = "push", about = "upload the local index to Dropbox")]
+    Push,
+    #[structopt(
+        name = "sync",
+        about = "apply what changed in Dropbox since reset-db or the last sync"
+    )]
+    Sync,
+}
+
+#[derive(StructOpt)]
+enum AuthSub {
+    #[structopt(name = "login", about = "authorize this app and store a refresh token")]
+    Login {
+        #[structopt(long, env = "DBX_API_APP_KEY", help = "Dropbox app key")]
+        app_key: String,
     },
+    #[structopt(name = "status", about = "show the stored authorization")]
+    Status,
+    #[structopt(name = "logout", about = "revoke and delete the stored authorization")]
+    Logout,
 }
 
-#[derive(Debug, Error)]
-enum MyError {
-    #[error("InvalidPathError: {0}")]
-    InvalidPathError(String),
-    #[error("InvalidExtensionString: {0}")]
-    InvalidExtensionString(String),
+/// `DBX_OAUTH_TOKEN` wins if set; otherwise the refresh token stored by `auth login` is used.
+fn token_source() -> Result<Arc<dyn TokenSource>> {
+    if let Ok(token) = env::var("DBX_OAUTH_TOKEN") {
+        return Ok(Arc::new(StaticToken(token)));
+    }
+    let authenticator = Authenticator::load(&credentials_path()?)?;
+    match env::var("DBX_BASE_URL") {
+        Ok(base_url) => Ok(Arc::new(authenticator.with_base_url(&base_url))),
+        Err(_) => Ok(Arc::new(authenticator)),
+    }
 }
 
-async fn reset_db(path: String) -> Result<()> {
-    println!("resetDB");
-    println!("{:?}", sqlite_reset_db("my-dropbox3.db3", &path).await);
-    let mut source_file = File::open("my-dropbox3.db3")?;
-    upload_file(source_file, "/my-dropbox2.db3".to_string())?;
-    Ok(())
+fn remote_store(
+    local_store: Option<&Path>,
+    max_attempts: u32,
+    limiter: Option<Arc<UploadLimiter>>,
+) -> Result<Arc<dyn RemoteStore>> {
+    if let Some(root) = local_store {
+        let store = Arc::new(LocalStore::new(root)?);
+        return Ok(match limiter {
+            Some(limiter) => Arc::new(LimitedStore::new(store, limiter)),
+            None => store,
+        });
+    }
+    let store = match env::var("DBX_BASE_URL") {
+        Ok(base_url) => DropboxStore::with_base_url(token_source()?, &base_url),
+        Err(_) => DropboxStore::new(token_source()?),
+    }
+    .with_retry(RetryPolicy::default().with_max_attempts(max_attempts));
+    Ok(match limiter {
+        Some(limiter) => Arc::new(store.with_limiter(limiter)),
+        None => Arc::new(store),
+    })
 }
 
-async fn upload(path: &Path) -> Result<()> {
-    println!("upload");
-    // let mut init = calc_starter(&path).await?;
-    let mut init = runner(&path).await?;
-    sort_calc(&mut init);
-    // println!("{:?}", init);
-    println!("sum: {}", sum_calc(&init));
-    // println!("{:?}", upload_files(init).await?);
-    upload_files(init).await?;
-    println!("ok?");
+/// The config file with the command line options applied on top.
+fn load_config(args: &Cli) -> Result<Config> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load(&config_path()?)?,
+    };
+    if let Some(dest_root) = &args.dest_root {
+        config.dest_root = dest_root.clone();
+    }
+    if let Some(name_template) = &args.name_template {
+        config.name_template = name_template.clone();
+    }
+    if let Some(subfolders) = &args.subfolders {
+        config.subfolders = Some(subfolders.clone());
+    }
+    if let Some(index) = &args.index {
+        config.index = index.clone();
+    }
+    if let Some(remote_index) = &args.remote_index {
+        config.remote_index = remote_index.clone();
+    }
+    if let Some(timezone) = &args.timezone {
+        config.timezone = timezone.clone();
+    }
+    config.validate()?;
+    Ok(config)
+}
+
+fn auth(sub: AuthSub) -> Result<()> {
+    let path = credentials_path()?;
+    match sub {
+        AuthSub::Login { app_key } => {
+            let base_url = env::var("DBX_BASE_URL").ok();
+            let authenticator = Authenticator::login(&app_key, &path, base_url.as_deref())?;
+            println!("{}", authenticator.status());
+        }
+        AuthSub::Status => match Authenticator::load(&path) {
+            Ok(authenticator) => println!("{}", authenticator.status()),
+            Err(_) => println!("not logged in"),
+        },
+        AuthSub::Logout => {
+            let authenticator = Authenticator::load(&path)?;
+            match env::var("DBX_BASE_URL") {
+                Ok(base_url) => authenticator.with_base_url(&base_url).logout()?,
+                Err(_) => authenticator.logout()?,
+            }
+            println!("logged out");
+        }
+    }
+    Ok(())
+}
 
+async fn reset_db(store: Arc<dyn RemoteStore>, path: String, config: &Config) -> Result<()> {
+    println!("resetDB");
+    sqlite_reset_db(store.clone(), &config.index, &path).await?;
+    push_index(store, &config.index, &config.remote_index)?;
     Ok(())
 }
-fn get_metadata(path: &Path) -> Result<()> {
-    println!("meta");
-    let ext = Extension::from_str(
-        path.extension()
-            .ok_or(MyError::InvalidPathError("invalid path".to_string()))
-            .and_then(|ext| {
-                ext.to_str()
-                    .ok_or(MyError::InvalidExtensionString(format!("{:?}", path)))
-            })?,
-    )?;
-    let mut file =
-        File::open(&path).with_context(|| format!("failed to open file: {:?}", path.to_str()))?;
-    let mut buff = BufReader::new(&file);
-    let digest = sha_256_digest(&mut buff);
-    // sha_256_digest2(&file);
-    match ext {
-        Extension::Jpeg => {
-            println!("pic");
-            println!("{:?}", get_datetime(&mut buff));
-            // get_file_metadata(&format!(
-            //     "/ファミリー ルーム/写真/{}",
-            //     path.to_str().unwrap()
-            // ));
+
+fn db(store: Arc<dyn RemoteStore>, sub: DbSub, config: &Config) -> Result<()> {
+    match sub {
+        DbSub::Pull => {
+            pull_index(store.as_ref(), &config.remote_index, &config.index)?;
+            println!("pulled {} into {}", config.remote_index, config.index);
+        }
+        DbSub::Push => {
+            push_index(store, &config.index, &config.remote_index)?;
+            println!("pushed {} to {}", config.index, config.remote_index);
         }
-        Extension::Mp4 => {
-            println!("mov");
-            println!("{:?}", get_mp4_datetime(&mut buff));
-            buff.seek(SeekFrom::Start(0))?;
-            // get_file_metadata(&format!(
-            //     "/ファミリー ルーム/動画/{}",
-            //     path.to_str().unwrap()
-            // ));
+        DbSub::Sync => {
+            let report = sync_db(store.as_ref(), &config.index)?;
+            println!(
+                "synced: {} updated, {} deleted",
+                report.updated, report.deleted
+            );
         }
-        _ => {}
     }
-    println!("digest: {:?}", HEXUPPER.encode(digest.unwrap().as_ref()));
-    println!("dpx_digest: {:?}", dpx_digest(&mut buff));
     Ok(())
 }
-fn sp(tx: Sender<i32>) {
-    let tx2 = tx.clone();
-    tokio::spawn(async move {
-        tx.send(1).await;
-        println!("spawn 1");
-        tokio::spawn(async move {
-            println!("spawn 2");
-            tx2.send(2).await;
-        });
-    });
+
+async fn scan(path: &Path, config: &Config, strict: bool, rehash: bool) -> Result<Scan> {
+    let cache = Arc::new(ScanCache::open(&config.index, rehash)?);
+    let mut scan = runner(path, config.zone()?, strict, Some(cache)).await?;
+    sort_calc(&mut scan.files);
+    println!("sum: {}", sum_calc(&scan.files));
+    for (path, claimed, detected) in &scan.mismatched {
+        eprintln!(
+            "warning: {} is {:?} by content but {:?} by extension",
+            path, detected, claimed
+        );
+    }
+    for file in scan
+        .files
+        .values()
+        .flat_map(|files| files.pic.iter().chain(files.mov.iter()))
+        .filter(|file| !file.date_source.is_primary())
+    {
+        eprintln!("date from {}: {}", file.date_source, file.path);
+    }
+    Ok(scan)
+}
+
+/// Prints the files a scan skipped as a table.
+fn print_failures(failed: &[FileFailure]) {
+    if failed.is_empty() {
+        return;
+    }
+    println!("unreadable files: {}", failed.len());
+    let rows = failed
+        .iter()
+        .map(|failure| {
+            vec![
+                failure.stage.to_string(),
+                failure.path.clone(),
+                failure.error.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    print_table(&["STAGE", "PATH", "ERROR"], &rows);
+}
+
+/// Prints `rows` under `headers` in left-aligned columns.
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let widths = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(std::iter::once(header.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let print_row = |cells: Vec<&str>| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+    print_row(headers.to_vec());
+    for row in rows {
+        print_row(row.iter().map(|cell| cell.as_str()).collect());
+    }
+}
+
+async fn upload(
+    store: Arc<dyn RemoteStore>,
+    path: &Path,
+    config: &Config,
+    strict: bool,
+    rehash: bool,
+) -> Result<()> {
+    println!("upload");
+    let scan = scan(path, config, strict, rehash).await?;
+    let report = upload_files(store, &config.index, scan.files, config).await?;
+    println!("uploaded: {}", report.uploaded.len());
+    print_failures(&scan.failed);
+    if report.failed.is_empty() {
+        return Ok(());
+    }
+    for (path, e) in &report.failed {
+        eprintln!("failed: {}: {}", path, e);
+    }
+    Err(anyhow::anyhow!(
+        "{} files failed to upload",
+        report.failed.len()
+    ))
+}
+
+/// Prints what `upload` would do without starting any upload session.
+async fn upload_dry_run(path: &Path, config: &Config, strict: bool, rehash: bool) -> Result<()> {
+    let scan = scan(path, config, strict, rehash).await?;
+    let plan = plan_upload(&config.index, &scan.files, config)?;
+    for (path, remote_path) in &plan.new {
+        println!("new: {} -> {}", path, remote_path);
+    }
+    for path in &plan.duplicates {
+        println!("duplicate: {}", path);
+    }
+    for path in &scan.unsupported {
+        println!("unsupported: {}", path);
+    }
+    println!(
+        "new: {}, duplicate: {}, unsupported: {}, unreadable: {}",
+        plan.new.len(),
+        plan.duplicates.len(),
+        scan.unsupported.len(),
+        scan.failed.len()
+    );
+    print_failures(&scan.failed);
+    Ok(())
 }
-async fn sp2() {
-    println!("sp2-0");
-    let ans = tokio::spawn(async { println!("sp2-1") });
-    println!("sp2-2");
-    tokio::join!(ans);
+
+/// Prints metadata and the planned remote name of each file, as a table or JSON.
+fn meta(paths: &[PathBuf], format: &str, config: &Config) -> Result<()> {
+    let infos = inspect(paths, &config.index, config)?;
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&infos)?);
+        return Ok(());
+    }
+    let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let rows = infos
+        .iter()
+        .map(|info| {
+            let details = &info.details;
+            let camera = [details.make.as_deref(), details.model.as_deref()]
+                .iter()
+                .flatten()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ");
+            vec![
+                info.path.clone(),
+                info.kind.clone(),
+                or_dash(info.capture_time.clone()),
+                or_dash(info.date_source.clone()),
+                info.timezone.clone(),
+                if camera.is_empty() {
+                    "-".to_string()
+                } else {
+                    camera
+                },
+                or_dash(
+                    details
+                        .gps
+                        .as_ref()
+                        .map(|gps| format!("{:.6},{:.6}", gps.latitude, gps.longitude)),
+                ),
+                or_dash(
+                    details
+                        .width
+                        .zip(details.height)
+                        .map(|(width, height)| format!("{}x{}", width, height)),
+                ),
+                or_dash(details.duration.map(|duration| format!("{:.1}s", duration))),
+                match (&info.remote_name, &info.error) {
+                    (Some(remote_name), _) => remote_name.clone(),
+                    _ if info.duplicate => "(duplicate)".to_string(),
+                    (None, Some(error)) => format!("({})", error),
+                    (None, None) => "-".to_string(),
+                },
+                or_dash(info.sha256.clone()),
+                or_dash(info.content_hash.clone()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    print_table(
+        &[
+            "PATH",
+            "TYPE",
+            "CAPTURE TIME",
+            "SOURCE",
+            "TIMEZONE",
+            "CAMERA",
+            "GPS",
+            "DIMENSIONS",
+            "DURATION",
+            "REMOTE NAME",
+            "SHA-256",
+            "CONTENT HASH",
+        ],
+        &rows,
+    );
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Cli::from_args();
+    let config = load_config(&args)?;
     match args.sub {
         Sub::ResetDb { path } => {
-            reset_db(path).await;
+            reset_db(
+                remote_store(args.local_store.as_deref(), args.max_attempts, None)?,
+                path,
+                &config,
+            )
+            .await?;
         }
-        Sub::Upload { path } => {
-            upload(&path).await;
+        Sub::Upload {
+            path,
+            dry_run: true,
+            strict,
+            rehash,
+            ..
+        } => {
+            upload_dry_run(&path, &config, strict, rehash).await?;
         }
-        Sub::Meta { path } => {
-            get_metadata(&path);
+        Sub::Upload {
+            path,
+            max_in_flight,
+            bandwidth,
+            dry_run: false,
+            strict,
+            rehash,
+        } => {
+            let limiter = Arc::new(UploadLimiter::new(max_in_flight, bandwidth));
+            upload(
+                remote_store(
+                    args.local_store.as_deref(),
+                    args.max_attempts,
+                    Some(limiter),
+                )?,
+                &path,
+                &config,
+                strict,
+                rehash,
+            )
+            .await?;
         }
-        Sub::Test { path } => {
-            sp2().await;
-            println!("test");
-            let (mut tx, mut rx): (Sender<i32>, Receiver<i32>) = channel(32);
-            sp(tx);
-            while let Some(message) = rx.recv().await {
-                println!("received: {}", message);
-                if message == 2 {
-                    break;
-                }
-            }
+        Sub::Meta { paths, format } => {
+            meta(&paths, &format, &config)?;
+        }
+        Sub::Auth(sub) => {
+            auth(sub)?;
+        }
+        Sub::Db(sub) => {
+            db(
+                remote_store(args.local_store.as_deref(), args.max_attempts, None)?,
+                sub,
+                &config,
+            )?;
         }
     };
-    // list_directory("/");
-    // let e = Extension::from_str(ext)?;
-    // match Extension::from_str(ext)? {
-    //     Extension::Jpeg => {
-    //         println!("ok")
-    //     }
-    //     // Err(e) => {
-    //     //     println!("ng")
-    //     // }
-    // }
-
-    // let content = std::fs::read_to_string(&path).with_context(|| {
-    //     format!(
-    //         "failed to open file: {}",
-    //         &path.to_str().unwrap_or("invalid path")
-    //     )
-    // })?;
-    // let content = std::fs::read_to_string(&args.path).with_context(|| {
-    //     format!(
-    //         "failed to open file: {}",
-    //         &args.path.to_str().unwrap_or("invalid path")
-    //     )
-    // })?;
-    // for line in content.lines() {
-    //     if line.contains(&args.pattern) {
-    //         println!("{}", line);
-    //     }
-    // }
     Ok(())
 }
@@ -1,15 +1,17 @@
+use crate::bmff;
 use crate::extension::Extension;
+use crate::riff;
 use anyhow::{Context, Result};
 use chrono::DateTime as ChronoDateTime;
-use chrono::{NaiveDateTime, TimeZone, Utc};
-use chrono_tz::Asia::Tokyo;
+use chrono::{Duration, FixedOffset, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
 use chrono_tz::Tz;
-use exif::{DateTime, In, Reader, Tag, Value};
-use mp4::creation_time;
-use mp4::Result as Mp4Result;
+use exif::{DateTime, Exif, Field, In, Reader, Tag, Value};
+use serde::Serialize;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
+use std::str::FromStr;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -18,44 +20,721 @@ enum MetaError {
     ParseDateTimeError(String),
 }
 
-pub fn datetime(mut buff: &mut BufReader<&File>, ext: &Extension) -> Result<ChronoDateTime<Tz>> {
-    match ext {
-        Extension::Jpeg => get_datetime(&mut buff),
-        Extension::Mp4 => get_mp4_datetime(&mut buff),
-        Other => Err(anyhow::anyhow!("no datetime for non image file")),
-    }
-    // reader: &mut BufReader<&File>
-}
-pub fn get_datetime(reader: &mut BufReader<&File>) -> Result<ChronoDateTime<Tz>> {
-    let exif = Reader::new().read_from_container(reader)?;
-    let date_time = exif
-        .get_field(Tag::DateTime, In::PRIMARY)
-        .with_context(|| format!("date tiem doesn't exist"))?;
-    let date_time_value: Result<exif::DateTime, anyhow::Error> = match &date_time.value {
-        Value::Ascii(d) => Ok(DateTime::from_ascii(&d[0])?),
-        _ => Err(MetaError::ParseDateTimeError(format!(
-            "{:?}",
-            &date_time.value
-        )))?,
-    };
-    let str = &format!("{} +09:00", date_time_value?.to_string());
-    let dt = ChronoDateTime::parse_from_str(&str, "%Y-%m-%d %H:%M:%S %z")?;
+/// Where a capture time came from, strongest evidence first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DateSource {
+    DateTimeOriginal,
+    /// `com.apple.quicktime.creationdate`, which carries the local offset.
+    QuickTimeCreationDate,
+    /// `©day` in the movie's user data.
+    Mp4Day,
+    Mp4CreationTime,
+    /// AVI `IDIT` chunk.
+    AviIdit,
+    /// AVI `ICRD` (creation date) in the `INFO` list.
+    AviIcrd,
+    DateTimeDigitized,
+    /// IFD0 `DateTime`, which editors rewrite on save.
+    DateTime,
+    Gps,
+    FileName,
+    Mtime,
+}
+
+impl DateSource {
+    /// Whether the time was read from where the camera records the moment of capture.
+    pub fn is_primary(&self) -> bool {
+        matches!(
+            self,
+            DateSource::DateTimeOriginal
+                | DateSource::QuickTimeCreationDate
+                | DateSource::Mp4Day
+                | DateSource::Mp4CreationTime
+                | DateSource::AviIdit
+                | DateSource::AviIcrd
+        )
+    }
+}
+
+impl fmt::Display for DateSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DateSource::DateTimeOriginal => "DateTimeOriginal",
+            DateSource::QuickTimeCreationDate => "com.apple.quicktime.creationdate",
+            DateSource::Mp4Day => "©day",
+            DateSource::Mp4CreationTime => "MP4 creation_time",
+            DateSource::AviIdit => "AVI IDIT",
+            DateSource::AviIcrd => "AVI ICRD",
+            DateSource::DateTimeDigitized => "DateTimeDigitized",
+            DateSource::DateTime => "DateTime",
+            DateSource::Gps => "GPS",
+            DateSource::FileName => "file name",
+            DateSource::Mtime => "mtime",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parses the names written by `Display`, as stored in the scan cache.
+impl FromStr for DateSource {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        [
+            DateSource::DateTimeOriginal,
+            DateSource::QuickTimeCreationDate,
+            DateSource::Mp4Day,
+            DateSource::Mp4CreationTime,
+            DateSource::AviIdit,
+            DateSource::AviIcrd,
+            DateSource::DateTimeDigitized,
+            DateSource::DateTime,
+            DateSource::Gps,
+            DateSource::FileName,
+            DateSource::Mtime,
+        ]
+        .iter()
+        .copied()
+        .find(|source| source.to_string() == s)
+        .ok_or_else(|| anyhow::anyhow!("unknown date source: {}", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureTime {
+    /// Local time where it was taken: with the offset recorded in the file if there is one,
+    /// in the default zone otherwise.
+    pub datetime: ChronoDateTime<FixedOffset>,
+    pub source: DateSource,
+}
+
+/// The capture time from the file's own metadata, falling back to a date in the file name
+/// and finally to its mtime.
+pub fn datetime(
+    path: &Path,
+    buff: &mut BufReader<&File>,
+    ext: &Extension,
+    zone: Tz,
+) -> Result<CaptureTime> {
+    Ok(datetime_and_model(path, buff, ext, zone)?.0)
+}
+
+/// `datetime`, and the camera model of a still, from a single read of its EXIF.
+pub fn datetime_and_model(
+    path: &Path,
+    buff: &mut BufReader<&File>,
+    ext: &Extension,
+    zone: Tz,
+) -> Result<(CaptureTime, Option<String>)> {
+    let (embedded, model) = match ext {
+        ext if ext.is_picture() => match read_exif(buff) {
+            Ok(exif) => (exif_datetime(&exif, zone), exif_model(&exif)),
+            Err(e) => (Err(e), None),
+        },
+        ext if ext.is_iso_movie() => (get_mp4_datetime(buff, zone), None),
+        Extension::Avi => (get_avi_datetime(buff, zone), None),
+        _ => (Err(anyhow::anyhow!("no datetime for non image file")), None),
+    };
+    buff.seek(SeekFrom::Start(0))?;
+    if let Ok(capture_time) = embedded {
+        return Ok((capture_time, model));
+    }
+    if let Some(naive) = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(file_name_datetime)
+    {
+        let capture_time = CaptureTime {
+            datetime: local_datetime(naive, None, zone)?,
+            source: DateSource::FileName,
+        };
+        return Ok((capture_time, model));
+    }
+    let mtime: ChronoDateTime<Utc> = buff.get_ref().metadata()?.modified()?.into();
+    let mtime = mtime.with_timezone(&zone);
+    let capture_time = CaptureTime {
+        datetime: mtime.with_timezone(&mtime.offset().fix()),
+        source: DateSource::Mtime,
+    };
+    Ok((capture_time, model))
+}
+
+/// EXIF capture time: `DateTimeOriginal`, `DateTimeDigitized`, `DateTime`, then GPS time,
+/// each with its matching offset tag, or else `OffsetTime`, if present.
+pub fn get_datetime(reader: &mut BufReader<&File>, zone: Tz) -> Result<CaptureTime> {
+    let exif = read_exif(reader)?;
     reader.seek(SeekFrom::Start(0))?;
-    Ok(dt.with_timezone(&Tokyo))
+    exif_datetime(&exif, zone)
+}
+
+fn exif_datetime(exif: &ExifData, zone: Tz) -> Result<CaptureTime> {
+    let candidates = [
+        (
+            DateSource::DateTimeOriginal,
+            Tag::DateTimeOriginal,
+            Tag::OffsetTimeOriginal,
+        ),
+        (
+            DateSource::DateTimeDigitized,
+            Tag::DateTimeDigitized,
+            Tag::OffsetTimeDigitized,
+        ),
+        (DateSource::DateTime, Tag::DateTime, Tag::OffsetTime),
+    ];
+    for (source, tag, offset_tag) in &candidates {
+        let mut date_time_value =
+            match ascii_field(exif, *tag).and_then(|d| DateTime::from_ascii(d).ok()) {
+                Some(date_time_value) => date_time_value,
+                None => continue,
+            };
+        // Many cameras write only `OffsetTime`, meant for every timestamp.
+        let offset = ascii_field(exif, *offset_tag).or_else(|| ascii_field(exif, Tag::OffsetTime));
+        if let Some(offset) = offset {
+            // A malformed offset just means falling back to `zone`.
+            let _ = date_time_value.parse_offset(offset);
+        }
+        let naive = match exif_naive_datetime(&date_time_value) {
+            Some(naive) => naive,
+            None => continue,
+        };
+        return Ok(CaptureTime {
+            datetime: local_datetime(naive, date_time_value.offset, zone)?,
+            source: *source,
+        });
+    }
+    let utc = gps_datetime(exif).context("date time doesn't exist")?;
+    let dt = Utc.from_utc_datetime(&utc).with_timezone(&zone);
+    Ok(CaptureTime {
+        datetime: dt.with_timezone(&dt.offset().fix()),
+        source: DateSource::Gps,
+    })
+}
+
+/// EXIF of a still: one TIFF structure, or CR3's separate TIFF per IFD.
+enum ExifData {
+    Single(Exif),
+    /// kamadak-exif reads every block as IFD0, so fields are matched by number within the
+    /// block of the tag's context.
+    Blocks(Vec<(exif::Context, Exif)>),
+}
+
+impl ExifData {
+    fn field(&self, tag: Tag) -> Option<&Field> {
+        match self {
+            ExifData::Single(exif) => exif.get_field(tag, In::PRIMARY),
+            ExifData::Blocks(blocks) => blocks
+                .iter()
+                .filter(|(context, _)| *context == tag.context())
+                .flat_map(|(_, exif)| exif.fields())
+                .find(|field| field.ifd_num == In::PRIMARY && field.tag.number() == tag.number()),
+        }
+    }
+}
+
+/// Canon's `uuid` box in a CR3 `moov`, holding the `CMT*` EXIF blocks.
+const CR3_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+/// Reads EXIF from JPEG, HEIF, TIFF-based RAWs, and the RAWs kamadak-exif doesn't recognize:
+/// ORF and RW2, which are TIFF with their own magic number, and CR3.
+fn read_exif(reader: &mut BufReader<&File>) -> Result<ExifData> {
+    let mut head = [0u8; 12];
+    reader.read_exact(&mut head)?;
+    reader.seek(SeekFrom::Start(0))?;
+    match &head {
+        [b'I', b'I', b'R', b'O', ..]
+        | [b'I', b'I', b'R', b'S', ..]
+        | [b'M', b'M', b'O', b'R', ..]
+        | [b'I', b'I', b'U', 0, ..] => {
+            let len = tiff_metadata_len(reader, head[0] == b'I')?;
+            let mut tiff = Vec::new();
+            reader.by_ref().take(len).read_to_end(&mut tiff)?;
+            let magic: &[u8] = if tiff[0] == b'I' {
+                &[0x2a, 0]
+            } else {
+                &[0, 0x2a]
+            };
+            tiff[2..4].copy_from_slice(magic);
+            Ok(ExifData::Single(Reader::new().read_raw(tiff)?))
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', b'c', b'r', b'x', b' '] => read_cr3_exif(reader),
+        _ => Ok(ExifData::Single(Reader::new().read_from_container(reader)?)),
+    }
+}
+
+/// How far into a TIFF-structured file its IFDs and their values reach, so the EXIF can be
+/// read without the image data. Follows the IFDs kamadak-exif parses.
+fn tiff_metadata_len<R: Read + Seek>(reader: &mut R, little_endian: bool) -> Result<u64> {
+    let u16_at = |b: &[u8]| {
+        let b = [b[0], b[1]];
+        if little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        }
+    };
+    let u32_at = |b: &[u8]| {
+        let b = [b[0], b[1], b[2], b[3]];
+        u64::from(if little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    };
+    // Exif and GPS IFDs hang off the main IFDs, the Interop IFD off the Exif IFD.
+    const TIFF_CHILDREN: &[u16] = &[0x8769, 0x8825];
+    const EXIF_CHILDREN: &[u16] = &[0xa005];
+    let mut header = [0u8; 8];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+    let mut end = 8;
+    let mut pending = vec![(u32_at(&header[4..]), TIFF_CHILDREN)];
+    let mut ifds = 0;
+    while let Some((offset, children)) = pending.pop() {
+        if offset == 0 {
+            continue;
+        }
+        ifds += 1;
+        if ifds > 32 {
+            return Err(anyhow::anyhow!("too many IFDs"));
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut count = [0u8; 2];
+        reader.read_exact(&mut count)?;
+        let count = u16_at(&count) as usize;
+        let mut ifd = vec![0u8; count * 12 + 4];
+        reader.read_exact(&mut ifd)?;
+        end = end.max(offset + 2 + ifd.len() as u64);
+        for entry in ifd[..count * 12].chunks(12) {
+            let unit = match u16_at(&entry[2..]) {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => 0,
+            };
+            let len = unit * u32_at(&entry[4..]);
+            if len > 4 {
+                end = end.max(u32_at(&entry[8..]) + len);
+            }
+            let tag = u16_at(entry);
+            if children.contains(&tag) {
+                let grandchildren = if tag == 0x8769 { EXIF_CHILDREN } else { &[] };
+                pending.push((u32_at(&entry[8..]), grandchildren));
+            }
+        }
+        // Only the main IFDs are chained.
+        if children == TIFF_CHILDREN {
+            pending.push((u32_at(&ifd[count * 12..]), TIFF_CHILDREN));
+        }
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(end)
+}
+
+fn read_cr3_exif(reader: &mut BufReader<&File>) -> Result<ExifData> {
+    let size = reader.get_ref().metadata()?.len();
+    let moov = bmff::find(reader, 0, size, b"moov")?.context("no moov box")?;
+    let mut canon = None;
+    for uuid in bmff::children(reader, moov.start, moov.end)?
+        .into_iter()
+        .filter(|header| &header.kind == b"uuid")
+    {
+        if bmff::read_prefix(reader, &uuid, 16)? == CR3_UUID {
+            canon = Some(uuid);
+            break;
+        }
+    }
+    let canon = canon.context("no Canon uuid box")?;
+    let mut blocks = Vec::new();
+    for header in bmff::children(reader, canon.start + 16, canon.end)? {
+        let context = match &header.kind {
+            b"CMT1" => exif::Context::Tiff,
+            b"CMT2" => exif::Context::Exif,
+            b"CMT4" => exif::Context::Gps,
+            _ => continue,
+        };
+        let tiff = bmff::read_content(reader, &header)?;
+        blocks.push((context, Reader::new().read_raw(tiff)?));
+    }
+    Ok(ExifData::Blocks(blocks))
+}
+
+fn ascii_field(exif: &ExifData, tag: Tag) -> Option<&[u8]> {
+    match exif.field(tag).map(|field| &field.value) {
+        Some(Value::Ascii(d)) => d.first().map(|d| d.as_slice()),
+        _ => None,
+    }
+}
+
+fn exif_naive_datetime(date_time_value: &DateTime) -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(
+        date_time_value.year as i32,
+        date_time_value.month as u32,
+        date_time_value.day as u32,
+    )?
+    .and_hms_opt(
+        date_time_value.hour as u32,
+        date_time_value.minute as u32,
+        date_time_value.second as u32,
+    )
+}
+
+/// `GPSDateStamp` ("YYYY:MM:DD") plus `GPSTimeStamp` (three rationals), in UTC.
+fn gps_datetime(exif: &ExifData) -> Option<NaiveDateTime> {
+    let date = std::str::from_utf8(ascii_field(exif, Tag::GPSDateStamp)?).ok()?;
+    let date = NaiveDate::parse_from_str(date.trim(), "%Y:%m:%d").ok()?;
+    match &exif.field(Tag::GPSTimeStamp)?.value {
+        Value::Rational(hms) if hms.len() == 3 => date.and_hms_opt(
+            hms[0].to_f64() as u32,
+            hms[1].to_f64() as u32,
+            hms[2].to_f64() as u32,
+        ),
+        _ => None,
+    }
 }
 
-pub fn get_mp4_datetime(reader: &mut BufReader<&File>) -> Result<ChronoDateTime<Tz>> {
+/// Finds a capture time in names like `IMG_20240501_123456`, `PXL_20240501_123456789`,
+/// `2024-05-01 12.34.56` or `IMG-20240501-WA0001` (date only, taken as midnight).
+pub fn file_name_datetime(stem: &str) -> Option<NaiveDateTime> {
+    let groups: Vec<&str> = stem
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|group| !group.is_empty())
+        .collect();
+    for start in 0..groups.len() {
+        let digits = groups[start..].concat();
+        if digits.len() < 8 || !(1990..=2100).contains(&digits[..4].parse::<i32>().unwrap_or(0)) {
+            continue;
+        }
+        if digits.len() >= 14 {
+            if let Ok(dt) = NaiveDateTime::parse_from_str(&digits[..14], "%Y%m%d%H%M%S") {
+                return Some(dt);
+            }
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(&digits[..8], "%Y%m%d") {
+            return Some(date.and_hms(0, 0, 0));
+        }
+    }
+    None
+}
+
+/// `naive` is wall-clock time; `offset` is in minutes east of UTC.
+fn local_datetime(
+    naive: NaiveDateTime,
+    offset: Option<i16>,
+    zone: Tz,
+) -> Result<ChronoDateTime<FixedOffset>> {
+    let dt = match offset {
+        Some(offset) => FixedOffset::east_opt(offset as i32 * 60)
+            .ok_or(MetaError::ParseDateTimeError(format!("offset {}", offset)))?
+            .from_local_datetime(&naive)
+            .single(),
+        // A time skipped by a DST change doesn't exist in `zone`; a repeated one is ambiguous.
+        None => zone
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|dt| dt.with_timezone(&dt.offset().fix())),
+    };
+    Ok(dt.ok_or(MetaError::ParseDateTimeError(format!(
+        "{} in {}",
+        naive, zone
+    )))?)
+}
+
+/// The EXIF camera model, if the file has one.
+pub fn camera_model(reader: &mut BufReader<&File>) -> Option<String> {
+    let model = read_exif(reader).ok().and_then(|exif| exif_model(&exif));
+    reader.seek(SeekFrom::Start(0)).ok()?;
+    model
+}
+
+fn exif_model(exif: &ExifData) -> Option<String> {
+    exif.field(Tag::Model)
+        .map(|field| {
+            field
+                .display_value()
+                .to_string()
+                .trim_matches('"')
+                .to_string()
+        })
+        .filter(|model| !model.is_empty())
+}
+
+/// Seconds from 1904-01-01, the QuickTime epoch, to 1970-01-01.
+const QUICKTIME_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+/// Movie capture time: `com.apple.quicktime.creationdate`, `©day`, then `mvhd` `creation_time`.
+pub fn get_mp4_datetime(reader: &mut BufReader<&File>, zone: Tz) -> Result<CaptureTime> {
     let file = reader.get_ref();
     let size = file.metadata()?.len();
+    let mtime = file
+        .metadata()?
+        .modified()
+        .ok()
+        .map(ChronoDateTime::<Utc>::from);
+
+    let moov = bmff::find(reader, 0, size, b"moov")?.context("no moov box")?;
+    let metadata = bmff::quicktime_metadata(reader, &moov)?;
+    if let Some(dt) = metadata
+        .get("com.apple.quicktime.creationdate")
+        .and_then(|value| metadata_datetime(value, zone))
+    {
+        return Ok(CaptureTime {
+            datetime: dt,
+            source: DateSource::QuickTimeCreationDate,
+        });
+    }
+    if let Some(dt) = bmff::user_data_text(reader, &moov, b"\xa9day")?
+        .and_then(|value| metadata_datetime(&value, zone))
+    {
+        return Ok(CaptureTime {
+            datetime: dt,
+            source: DateSource::Mp4Day,
+        });
+    }
+    let header = bmff::movie_header(reader, &moov)?;
+    let seconds = match header.creation_time {
+        0 => Err(anyhow::anyhow!("creation_time is not set"))?,
+        // By the spec a 1904-based count, which is past the offset for any date after 1970;
+        // some writers count from 1970 instead.
+        t if t >= QUICKTIME_EPOCH_OFFSET => t - QUICKTIME_EPOCH_OFFSET,
+        t => t,
+    };
+    let naive = NaiveDateTime::from_timestamp_opt(seconds as i64, 0).ok_or(
+        MetaError::ParseDateTimeError(format!("creation_time {}", seconds)),
+    )?;
+    let duration = match header.timescale {
+        0 => 0,
+        timescale => (header.duration / timescale as u64) as i64,
+    };
+    Ok(CaptureTime {
+        datetime: mvhd_datetime(naive, Duration::seconds(duration), mtime, zone)?,
+        source: DateSource::Mp4CreationTime,
+    })
+}
+
+/// `mvhd` times should be UTC, but some Android phones write local wall-clock time. The file
+/// is last written when recording stops, so if only the local reading ends near the mtime,
+/// take it as local.
+fn mvhd_datetime(
+    naive: NaiveDateTime,
+    duration: Duration,
+    mtime: Option<ChronoDateTime<Utc>>,
+    zone: Tz,
+) -> Result<ChronoDateTime<FixedOffset>> {
+    let utc = Utc.from_utc_datetime(&naive).with_timezone(&zone);
+    let utc = utc.with_timezone(&utc.offset().fix());
+    let local = local_datetime(naive, None, zone)?;
+    let ends_at_mtime = |start: ChronoDateTime<FixedOffset>| match mtime {
+        Some(mtime) => {
+            let elapsed = mtime.signed_duration_since(start);
+            elapsed >= Duration::zero() && elapsed <= duration + Duration::minutes(2)
+        }
+        None => false,
+    };
+    if !ends_at_mtime(utc) && ends_at_mtime(local) {
+        Ok(local)
+    } else {
+        Ok(utc)
+    }
+}
+
+/// ISO 8601 times from movie metadata, such as `2024-05-01T12:34:56+0900`. Without an offset
+/// the time is taken in `zone`; dates without a time are too coarse to use.
+fn metadata_datetime(value: &str, zone: Tz) -> Option<ChronoDateTime<FixedOffset>> {
+    let value = value.trim().trim_end_matches('\0');
+    if let Ok(dt) = ChronoDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f%z") {
+        return Some(dt);
+    }
+    // UTC says nothing about where it was taken, so it is shown in `zone` like `mvhd` times.
+    if let Some(utc) = value.strip_suffix('Z') {
+        let naive = NaiveDateTime::parse_from_str(utc, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+        let dt = Utc.from_utc_datetime(&naive).with_timezone(&zone);
+        return Some(dt.with_timezone(&dt.offset().fix()));
+    }
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+    local_datetime(naive, None, zone).ok()
+}
+
+/// AVI capture time from `IDIT`, then `ICRD`. Both are the camera's local time, as text such
+/// as `THU OCT 26 16:46:04 2006` or `2006:10:26 16:46:04`.
+pub fn get_avi_datetime(reader: &mut BufReader<&File>, zone: Tz) -> Result<CaptureTime> {
+    let chunks = riff::find_chunks(reader, &[b"IDIT", b"ICRD"])?;
+    for (id, source) in &[
+        (b"IDIT", DateSource::AviIdit),
+        (b"ICRD", DateSource::AviIcrd),
+    ] {
+        if let Some(naive) = chunks.get(*id).and_then(|value| avi_naive_datetime(value)) {
+            return Ok(CaptureTime {
+                datetime: local_datetime(naive, None, zone)?,
+                source: *source,
+            });
+        }
+    }
+    Err(anyhow::anyhow!("no IDIT or ICRD chunk"))
+}
+
+fn avi_naive_datetime(value: &[u8]) -> Option<NaiveDateTime> {
+    let value = String::from_utf8_lossy(value);
+    // Cameras pad with NULs and newlines, and ctime pads single-digit days with a space.
+    let value = value
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    [
+        "%a %b %d %H:%M:%S %Y",
+        "%Y:%m:%d %H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y/%m/%d %H:%M:%S",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(&value, format).ok())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Gps {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Meters above sea level.
+    pub altitude: Option<f64>,
+}
+
+/// Descriptive metadata shown by `meta`; each field is `None` when the file doesn't have it.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Details {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub gps: Option<Gps>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Seconds, for movies.
+    pub duration: Option<f64>,
+}
+
+/// Best effort: what can't be read is left out.
+pub fn details(reader: &mut BufReader<&File>, ext: &Extension) -> Details {
+    let details = match ext {
+        ext if ext.is_picture() => read_exif(reader).map(|exif| exif_details(&exif)),
+        ext if ext.is_iso_movie() => mp4_details(reader),
+        Extension::Avi => avi_details(reader),
+        _ => Ok(Details::default()),
+    };
+    let _ = reader.seek(SeekFrom::Start(0));
+    details.unwrap_or_default()
+}
+
+fn exif_details(exif: &ExifData) -> Details {
+    let text = |tag| {
+        ascii_field(exif, tag)
+            .map(|value| String::from_utf8_lossy(value).trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let uint = |tags: &[Tag]| {
+        tags.iter()
+            .find_map(|tag| exif.field(*tag).and_then(|field| field.value.get_uint(0)))
+    };
+    let rationals = |tag| match &exif.field(tag)?.value {
+        Value::Rational(values) => Some(values.iter().map(|v| v.to_f64()).collect::<Vec<_>>()),
+        _ => None,
+    };
+    let degrees = |tag, ref_tag, negative: u8| {
+        let dms = rationals(tag).filter(|dms| dms.len() == 3)?;
+        let degrees = dms[0] + dms[1] / 60.0 + dms[2] / 3600.0;
+        match ascii_field(exif, ref_tag).and_then(|r| r.first()) {
+            Some(r) if *r == negative => Some(-degrees),
+            _ => Some(degrees),
+        }
+    };
+    let gps = match (
+        degrees(Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S'),
+        degrees(Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W'),
+    ) {
+        (Some(latitude), Some(longitude)) => Some(Gps {
+            latitude,
+            longitude,
+            altitude: rationals(Tag::GPSAltitude)
+                .and_then(|altitude| altitude.first().copied())
+                .map(|altitude| match uint(&[Tag::GPSAltitudeRef]) {
+                    // 1 means below sea level.
+                    Some(1) => -altitude,
+                    _ => altitude,
+                }),
+        }),
+        _ => None,
+    };
+    Details {
+        make: text(Tag::Make),
+        model: text(Tag::Model),
+        gps,
+        width: uint(&[Tag::PixelXDimension, Tag::ImageWidth]),
+        height: uint(&[Tag::PixelYDimension, Tag::ImageLength]),
+        duration: None,
+    }
+}
 
-    let mp4 = mp4::Mp4Reader::read_header(reader, size)?;
-    // let mp4 = mp4::Mp4Reader::read_header(reader, 4)?;
-    // Jan 1, 1970 UTC - Jan 1, 1904 UTC = 2082844800
-    // const UTC_EPOCH_DIFF: u64 = 2082844800;
-    // 仕様だと1904からの経過秒数だが、実際にはunix epoch timeが入っている
-    let dt = Utc.timestamp(creation_time(mp4.moov.mvhd.modification_time) as i64, 0);
-    let dt2 = dt.with_timezone(&Tokyo);
+fn mp4_details(reader: &mut BufReader<&File>) -> Result<Details> {
+    let size = reader.get_ref().metadata()?.len();
+    let moov = bmff::find(reader, 0, size, b"moov")?.context("no moov box")?;
+    let metadata = bmff::quicktime_metadata(reader, &moov)?;
+    let location = match metadata.get("com.apple.quicktime.location.ISO6709") {
+        Some(location) => Some(location.clone()),
+        None => bmff::user_data_text(reader, &moov, b"\xa9xyz")?,
+    };
+    let header = bmff::movie_header(reader, &moov)?;
+    let dimensions = bmff::track_dimensions(reader, &moov)?;
+    Ok(Details {
+        make: metadata.get("com.apple.quicktime.make").cloned(),
+        model: metadata.get("com.apple.quicktime.model").cloned(),
+        gps: location.as_deref().and_then(iso6709),
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        duration: match header.timescale {
+            0 => None,
+            timescale => Some(header.duration as f64 / timescale as f64),
+        },
+    })
+}
+
+/// ISO 6709 decimal degrees as written by phones, e.g. `+35.6895+139.6917+040.000/`.
+fn iso6709(value: &str) -> Option<Gps> {
+    let value = value.trim().trim_end_matches('/');
+    let mut numbers = Vec::new();
+    let mut start = 0;
+    for (i, c) in value.char_indices().skip(1) {
+        if c == '+' || c == '-' {
+            numbers.push(value[start..i].parse::<f64>().ok()?);
+            start = i;
+        }
+    }
+    numbers.push(value[start..].parse::<f64>().ok()?);
+    match numbers.as_slice() {
+        [latitude, longitude, rest @ ..] if rest.len() <= 1 => Some(Gps {
+            latitude: *latitude,
+            longitude: *longitude,
+            altitude: rest.first().copied(),
+        }),
+        _ => None,
+    }
+}
 
-    // reader.seek(SeekFrom::Start(0))?;
-    Ok(dt2)
+fn avi_details(reader: &mut BufReader<&File>) -> Result<Details> {
+    let chunks = riff::find_chunks(reader, &[b"avih"])?;
+    let avih = chunks.get(b"avih").context("no avih chunk")?;
+    let le32 = |at: usize| -> Option<u32> {
+        let bytes = avih.get(at..at + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    Ok(Details {
+        width: le32(32).filter(|width| *width > 0),
+        height: le32(36).filter(|height| *height > 0),
+        // Microseconds per frame times the number of frames.
+        duration: match (le32(0), le32(16)) {
+            (Some(per_frame), Some(frames)) if per_frame > 0 => {
+                Some(per_frame as f64 * frames as f64 / 1_000_000.0)
+            }
+            _ => None,
+        },
+        ..Details::default()
+    })
 }
@@ -0,0 +1,93 @@
+use dropbox_sdk::Error;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::thread;
+use std::time::Duration;
+
+/// How often and how patiently to retry Dropbox calls that failed transiently.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of tries, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Calls `f` until it succeeds, fails with an error that isn't worth retrying,
+    /// or `max_attempts` is used up.
+    pub fn run<T>(&self, mut f: impl FnMut() -> dropbox_sdk::Result<T>) -> dropbox_sdk::Result<T> {
+        let mut attempt = 1;
+        loop {
+            let e = match f() {
+                Err(e) if attempt < self.max_attempts => e,
+                result => return result,
+            };
+            let delay = match self.retry_delay(&e, attempt) {
+                Some(delay) => delay,
+                None => return Err(e),
+            };
+            eprintln!(
+                "retrying in {:?} (attempt {}/{}): {}",
+                delay,
+                attempt + 1,
+                self.max_attempts,
+                e
+            );
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// How long to wait before retrying after `e`, or `None` if `e` isn't transient.
+    fn retry_delay(&self, e: &Error, attempt: u32) -> Option<Duration> {
+        match e {
+            // Honor the server's Retry-After, but spread out the requests that were
+            // all rejected at the same time so they don't come back at once.
+            Error::RateLimited {
+                retry_after_seconds,
+                ..
+            } => Some(
+                Duration::from_secs(*retry_after_seconds as u64)
+                    + self.base_delay.mul_f64(random_fraction()),
+            ),
+            Error::ServerError(_) | Error::HttpClient(_) => Some(self.backoff(attempt)),
+            Error::UnexpectedHttpError { code, .. } if *code == 429 || *code >= 500 => {
+                Some(self.backoff(attempt))
+            }
+            _ => None,
+        }
+    }
+
+    /// "Full jitter": a random delay up to `base_delay * 2^(attempt - 1)`, capped at `max_delay`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .checked_mul(1 << (attempt - 1).min(16))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        ceiling.mul_f64(random_fraction())
+    }
+}
+
+fn random_fraction() -> f64 {
+    let mut buf = [0u8; 4];
+    match SystemRandom::new().fill(&mut buf) {
+        Ok(()) => u32::from_le_bytes(buf) as f64 / u32::MAX as f64,
+        Err(_) => 1.0,
+    }
+}
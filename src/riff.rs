@@ -0,0 +1,64 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Chunks outside `movi` are headers and metadata; anything bigger is not what we want.
+const MAX_CHUNK_LEN: u32 = 1024 * 1024;
+
+/// The first chunk of each of `ids` in a RIFF file such as AVI, searching nested `LIST`s but
+/// not the `movi` list holding the frames.
+pub fn find_chunks<R: Read + Seek>(
+    reader: &mut R,
+    ids: &[&[u8; 4]],
+) -> Result<HashMap<[u8; 4], Vec<u8>>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" {
+        Err(anyhow::anyhow!("not a RIFF file"))?
+    }
+    let end = 8 + u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+    let mut found = HashMap::new();
+    walk(reader, 12, end, ids, &mut found)?;
+    Ok(found)
+}
+
+fn walk<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    end: u64,
+    ids: &[&[u8; 4]],
+    found: &mut HashMap<[u8; 4], Vec<u8>>,
+) -> Result<()> {
+    let mut pos = start;
+    while pos + 8 <= end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 8];
+        if reader.read_exact(&mut header).is_err() {
+            // Truncated recordings just end early.
+            break;
+        }
+        let id = [header[0], header[1], header[2], header[3]];
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if &id == b"LIST" {
+            let mut list_type = [0u8; 4];
+            reader.read_exact(&mut list_type)?;
+            if &list_type != b"movi" {
+                walk(
+                    reader,
+                    pos + 12,
+                    (pos + 8 + size as u64).min(end),
+                    ids,
+                    found,
+                )?;
+            }
+        } else if ids.contains(&&id) && !found.contains_key(&id) && size <= MAX_CHUNK_LEN {
+            let mut data = vec![0u8; size as usize];
+            reader.read_exact(&mut data)?;
+            found.insert(id, data);
+        }
+        // Chunks are padded to an even length.
+        pos += 8 + size as u64 + (size as u64 & 1);
+    }
+    Ok(())
+}
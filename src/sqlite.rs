@@ -1,51 +1,43 @@
-use crate::dropbox::{get_oauth2_token, list_directory2, oauth2};
-use anyhow::Result;
-use dropbox_sdk::default_client::UserAuthDefaultClient;
+use crate::dropbox::{list_directory2, upload_file, Resume};
+use crate::extension::Extension;
+use crate::meta::DateSource;
+use crate::store::{RemoteFile, RemoteStore};
+use anyhow::{Context, Result};
+use chrono::Utc;
 use rusqlite::types::ToSqlOutput;
-use rusqlite::{params, Connection, Result as SqResult, ToSql, NO_PARAMS};
-use std::fs;
+use rusqlite::{
+    params, Connection, OpenFlags, OptionalExtension, Result as SqResult, ToSql, Transaction,
+    NO_PARAMS,
+};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
 use tokio::sync::mpsc;
 
-pub async fn reset_db(path: &str, source: &str) -> Result<()> {
-    let _ = fs::remove_file(path);
-    let conn = Connection::open(path)?;
-    conn.execute(
-        "CREATE TABLE files (
-            name TEXT,
-            hash TEXT 
-            );",
-        params![],
-    );
-    let data = vec![
-        FileData {
-            name: "1.jpg".to_string(),
-            hash: "1".to_string(),
-        },
-        FileData {
-            name: "2.mp4".to_string(),
-            hash: "2".to_string(),
-        },
-    ];
-    // for d in data {
-    //     insert(&conn, &d)?;
-    // }
-    // let result: SqResult<i32> =
-    //     conn.query_row("SELECT COUNT(*) FROM files;", NO_PARAMS, |row| row.get(0));
-    // println!("{:?}", result);
-    let (mut tx, mut rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel(32);
-    // let client = UserAuthDefaultClient::new(get_oauth2_token());
-    // let client = UserAuthDefaultClient::new(oauth2());
-    list_directory2(source, tx);
+/// Rebuilds the files and copies of the index at `path` from the listing of `source`, saving the
+/// cursor `sync_db` continues from. The old rows stay until the listing has finished, and the
+/// scan cache and upload sessions are kept.
+pub async fn reset_db(store: Arc<dyn RemoteStore>, path: &str, source: &str) -> Result<()> {
+    let mut conn = connection(path)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch("DELETE FROM files; DELETE FROM file_copies;")?;
+    let (tx_message, mut rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel(32);
+    list_directory2(store, source, tx_message);
     while let Some(message) = rx.recv().await {
         match message {
-            Message::Finish => {}
+            Message::Finish(cursor) => save_cursor(&tx, source, &cursor)?,
             Message::Abort(e) => return Err(anyhow::anyhow!(format!("{}", e))),
-            Message::Progress(name, hash) => {
+            Message::Progress(file) => {
                 let data = FileData {
-                    name: name,
-                    hash: hash,
+                    file_type: Extension::from_path(Path::new(&file.name))
+                        .ok()
+                        .and_then(FileType::of),
+                    ..FileData::from_remote(&file)?
                 };
-                match insert(&conn, &data) {
+                match index_file(&tx, &data) {
                     Ok(_) => {}
                     Err(e) => {
                         eprintln!("insert error: file: {}, error: {}", data.name, e)
@@ -54,49 +46,742 @@ pub async fn reset_db(path: &str, source: &str) -> Result<()> {
             }
         }
     }
+    tx.commit()?;
+    Ok(())
+}
+
+fn save_cursor(conn: &Connection, source: &str, cursor: &str) -> Result<()> {
+    conn.execute("DELETE FROM list_cursor;", NO_PARAMS)?;
+    conn.execute(
+        "INSERT INTO list_cursor (source, cursor) VALUES (?1, ?2);",
+        params![source, cursor],
+    )?;
+    Ok(())
+}
+
+/// What `sync_db` changed in the index.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub updated: usize,
+    pub deleted: usize,
+}
+
+/// Applies what changed in Dropbox since the cursor saved by `reset_db` or the last sync.
+/// Each page is committed with its cursor, so an interrupted sync resumes where it stopped.
+///
+/// The index keeps one path per content and remembers the other paths it was seen at. A copy
+/// doesn't take over the indexed path, and when that path goes, the row moves to a copy that
+/// Dropbox still has.
+pub fn sync_db(store: &dyn RemoteStore, path: &str) -> Result<SyncReport> {
+    let mut conn = connection(path)?;
+    let mut cursor: String = conn
+        .query_row("SELECT cursor FROM list_cursor;", NO_PARAMS, |row| {
+            row.get(0)
+        })
+        .optional()?
+        .ok_or_else(|| anyhow::anyhow!("no listing cursor in {}; run reset-db first", path))?;
+    let mut report = SyncReport::default();
+    loop {
+        let page = store.list_folder_continue(&cursor)?;
+        let tx = conn.transaction()?;
+        // A deleted folder only comes as one entry, so its contents go with it.
+        for deleted in &page.deleted {
+            tx.execute(
+                "DELETE FROM file_copies WHERE lower(path) = lower(?1)
+                OR substr(lower(path), 1, length(?1) + 1) = lower(?1) || '/';",
+                params![deleted],
+            )?;
+            let hashes = column(
+                &tx,
+                "SELECT content_hash FROM files WHERE lower(path) = lower(?1)
+                OR substr(lower(path), 1, length(?1) + 1) = lower(?1) || '/';",
+                params![deleted],
+            )?;
+            for hash in hashes {
+                if !move_to_copy(&tx, store, &hash)? {
+                    tx.execute("DELETE FROM files WHERE content_hash = ?1;", params![hash])?;
+                    report.deleted += 1;
+                }
+            }
+        }
+        for file in &page.entries {
+            let data = FileData {
+                file_type: Extension::from_path(Path::new(&file.name))
+                    .ok()
+                    .and_then(FileType::of),
+                ..FileData::from_remote(file)?
+            };
+            tx.execute(
+                "DELETE FROM file_copies WHERE path = ?1;",
+                params![data.path],
+            )?;
+            // The path now holds other content; what it held before may still be elsewhere.
+            let previous = column(
+                &tx,
+                "SELECT content_hash FROM files WHERE lower(path) = lower(?1)
+                AND content_hash <> ?2;",
+                params![data.path, data.content_hash],
+            )?;
+            for hash in previous {
+                if !move_to_copy(&tx, store, &hash)? {
+                    tx.execute("DELETE FROM files WHERE content_hash = ?1;", params![hash])?;
+                }
+            }
+            if index_file(&tx, &data)? {
+                report.updated += 1;
+            }
+        }
+        tx.execute("UPDATE list_cursor SET cursor = ?1;", params![page.cursor])?;
+        tx.commit()?;
+        if !page.has_more {
+            return Ok(report);
+        }
+        cursor = page.cursor;
+    }
+}
+
+fn column(conn: &Connection, sql: &str, params: &[&dyn ToSql]) -> Result<Vec<String>> {
+    let mut statement = conn.prepare(sql)?;
+    let values = statement
+        .query_map(params, |row| row.get(0))?
+        .collect::<SqResult<Vec<String>>>()?;
+    Ok(values)
+}
 
+/// Points the row of `hash` at a recorded copy that Dropbox still has with the same content.
+/// Copies found gone or changed are forgotten. Returns whether the row moved.
+fn move_to_copy(conn: &Connection, store: &dyn RemoteStore, hash: &str) -> Result<bool> {
+    let copies = column(
+        conn,
+        "SELECT path FROM file_copies WHERE content_hash = ?1 ORDER BY path;",
+        params![hash],
+    )?;
+    for path in copies {
+        conn.execute("DELETE FROM file_copies WHERE path = ?1;", params![path])?;
+        let file = match store.get_metadata(&path)? {
+            Some(file) if file.content_hash.as_deref() == Some(hash) => file,
+            _ => continue,
+        };
+        conn.execute(
+            "UPDATE files SET name = ?1, path = ?2, server_modified = ?3, rev = ?4
+            WHERE content_hash = ?5;",
+            params![
+                file.name,
+                file.path_display,
+                file.server_modified,
+                file.rev,
+                hash
+            ],
+        )?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Uploads the index at `path` to `remote_path` with its listing cursor, leaving out the scan
+/// cache and upload sessions, which only mean something on this machine.
+pub fn push_index(store: Arc<dyn RemoteStore>, path: &str, remote_path: &str) -> Result<()> {
+    let snapshot = format!("{}.push", path);
+    let _ = fs::remove_file(&snapshot);
+    let result = write_snapshot(path, &snapshot)
+        .and_then(|_| upload_file(store, File::open(&snapshot)?, remote_path.to_string()));
+    let _ = fs::remove_file(&snapshot);
+    result
+}
+
+fn write_snapshot(path: &str, snapshot: &str) -> Result<()> {
+    connection(path)?.execute("VACUUM INTO ?1;", params![snapshot])?;
+    Connection::open(snapshot)?.execute_batch(
+        "DELETE FROM upload_sessions;
+        DELETE FROM scan_cache;
+        VACUUM;",
+    )?;
+    Ok(())
+}
+
+/// Replaces the files, their copies and the listing cursor of the index at `path` with those of
+/// the index at `remote_path`. This machine's scan cache and upload sessions are kept.
+pub fn pull_index(store: &dyn RemoteStore, remote_path: &str, path: &str) -> Result<()> {
+    let data = store
+        .download(remote_path)?
+        .ok_or_else(|| anyhow::anyhow!("no index at {}", remote_path))?;
+    let pulled = format!("{}.pull", path);
+    fs::write(&pulled, &data)?;
+    let result = replace_files(path, &pulled, remote_path);
+    let _ = fs::remove_file(&pulled);
+    result
+}
+
+fn replace_files(path: &str, pulled: &str, remote_path: &str) -> Result<()> {
+    // Also brings an index pushed by an older version up to date.
+    drop(connection(pulled).with_context(|| format!("invalid index: {}", remote_path))?);
+    let mut conn = connection(path)?;
+    conn.execute("ATTACH DATABASE ?1 AS pulled;", params![pulled])?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "DELETE FROM main.files;
+        INSERT INTO main.files
+        (name, content_hash, path, size, file_type, capture_time, server_modified, rev,
+        uploaded_at)
+        SELECT name, content_hash, path, size, file_type, capture_time, server_modified, rev,
+        uploaded_at
+        FROM pulled.files;
+        DELETE FROM main.file_copies;
+        INSERT INTO main.file_copies (path, content_hash)
+        SELECT path, content_hash FROM pulled.file_copies;
+        DELETE FROM main.list_cursor;
+        INSERT INTO main.list_cursor (source, cursor) SELECT source, cursor FROM pulled.list_cursor;",
+    )?;
+    tx.commit()?;
+    conn.execute("DETACH DATABASE pulled;", NO_PARAMS)?;
     Ok(())
 }
 
 pub enum Message {
-    Finish,
+    /// The listing is complete; carries the cursor to continue it from later.
+    Finish(String),
     Abort(String),
-    Progress(String, String),
+    Progress(RemoteFile),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
     Picture,
     Movie,
 }
+
+impl FileType {
+    pub fn of(ext: Extension) -> Option<Self> {
+        match ext {
+            ext if ext.is_picture() => Some(FileType::Picture),
+            ext if ext.is_movie() => Some(FileType::Movie),
+            _ => None,
+        }
+    }
+}
+
 impl ToSql for FileType {
     fn to_sql(&self) -> SqResult<ToSqlOutput<'_>> {
         match self {
-            Picture => Ok(ToSqlOutput::from("picture")),
-            Movie => Ok(ToSqlOutput::from("movie")),
+            FileType::Picture => Ok(ToSqlOutput::from("picture")),
+            FileType::Movie => Ok(ToSqlOutput::from("movie")),
         }
     }
 }
+
+/// A row of `files`: a file on Dropbox, known by its content hash.
 pub struct FileData {
     name: String,
-    hash: String,
+    content_hash: String,
+    /// The remote path.
+    path: Option<String>,
+    size: Option<i64>,
+    file_type: Option<FileType>,
+    /// Local time of capture, as `DATETIME_FORMAT`.
+    capture_time: Option<String>,
+    server_modified: Option<String>,
+    rev: Option<String>,
+    uploaded_at: Option<String>,
+}
+
+impl FileData {
+    fn from_remote(file: &RemoteFile) -> Result<Self> {
+        Ok(Self {
+            name: file.name.clone(),
+            content_hash: file.content_hash.clone().ok_or(anyhow::anyhow!(
+                "content hash was empty: {}",
+                file.path_display
+            ))?,
+            path: Some(file.path_display.clone()),
+            size: Some(file.size as i64),
+            file_type: None,
+            capture_time: None,
+            server_modified: file.server_modified.clone(),
+            rev: file.rev.clone(),
+            uploaded_at: None,
+        })
+    }
 }
+
+/// Content hashes are unique, so a file already indexed is updated rather than added twice.
 fn insert(conn: &Connection, data: &FileData) -> Result<()> {
     conn.execute(
-        "INSERT INTO files (name,  hash) VALUES (?1, ?2);",
-        params![data.name, data.hash],
+        "INSERT INTO files
+        (name, content_hash, path, size, file_type, capture_time, server_modified, rev,
+        uploaded_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        ON CONFLICT (content_hash) DO UPDATE SET
+        name = excluded.name,
+        path = excluded.path,
+        size = excluded.size,
+        file_type = coalesce(excluded.file_type, file_type),
+        capture_time = coalesce(excluded.capture_time, capture_time),
+        server_modified = excluded.server_modified,
+        rev = excluded.rev,
+        uploaded_at = coalesce(excluded.uploaded_at, uploaded_at);",
+        params![
+            data.name,
+            data.content_hash,
+            data.path,
+            data.size,
+            data.file_type,
+            data.capture_time,
+            data.server_modified,
+            data.rev,
+            data.uploaded_at
+        ],
+    )?;
+    Ok(())
+}
+
+/// Indexes `data`, or only records its path as a copy if the content is already indexed at
+/// another path. Returns whether the row was written.
+fn index_file(conn: &Connection, data: &FileData) -> Result<bool> {
+    let indexed_path: Option<Option<String>> = conn
+        .query_row(
+            "SELECT path FROM files WHERE content_hash = ?1;",
+            params![data.content_hash],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let (Some(Some(indexed_path)), Some(path)) = (&indexed_path, &data.path) {
+        if !indexed_path.eq_ignore_ascii_case(path) {
+            conn.execute(
+                "INSERT OR REPLACE INTO file_copies (path, content_hash) VALUES (?1, ?2);",
+                params![path, data.content_hash],
+            )?;
+            return Ok(false);
+        }
+    }
+    insert(conn, data)?;
+    Ok(true)
+}
+
+/// Schema changes in order; a database at `user_version` N has had the first N applied.
+const MIGRATIONS: &[fn(&Transaction) -> Result<()>] = &[
+    index_files_by_content_hash,
+    add_upload_sessions,
+    add_scan_cache,
+    add_list_cursor,
+    add_session_stamps,
+    add_file_copies,
+];
+
+/// Opens the index at `path` without writing to it, or an empty one if there is no file yet.
+/// An index from an earlier version has to be upgraded by a command that writes it first.
+pub fn read_only_connection(path: &str) -> Result<Connection> {
+    if !Path::new(path).exists() {
+        let mut conn = Connection::open_in_memory()?;
+        migrate(&mut conn)?;
+        return Ok(conn);
+    }
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let version: i64 = conn.query_row("PRAGMA user_version;", NO_PARAMS, |row| row.get(0))?;
+    if version as usize != MIGRATIONS.len() {
+        Err(anyhow::anyhow!(
+            "index {} has schema version {}, not {}; run upload or reset-db to upgrade it",
+            path,
+            version,
+            MIGRATIONS.len()
+        ))?
+    }
+    Ok(conn)
+}
+
+/// Brings the database up to the current schema, each step in its own transaction.
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version;", NO_PARAMS, |row| row.get(0))?;
+    if version as usize > MIGRATIONS.len() {
+        Err(anyhow::anyhow!(
+            "index schema version {} is newer than this program knows ({})",
+            version,
+            MIGRATIONS.len()
+        ))?
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        migration(&tx).with_context(|| format!("migration to schema version {}", i + 1))?;
+        tx.execute_batch(&format!("PRAGMA user_version = {};", i + 1))?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Version 1: replaces the unkeyed `files(name, hash, ...)` of earlier versions, keeping one
+/// row per content hash.
+fn index_files_by_content_hash(tx: &Transaction) -> Result<()> {
+    // Databases from before `path`, `size` and `uploaded_at` existed lack those columns.
+    tx.execute_batch("CREATE TABLE IF NOT EXISTS files (name TEXT, hash TEXT);")?;
+    add_missing_columns(
+        tx,
+        "files",
+        &[
+            ("path", "TEXT"),
+            ("size", "INTEGER"),
+            ("uploaded_at", "TEXT"),
+        ],
+    )?;
+    tx.execute_batch(
+        "CREATE TABLE indexed_files (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        content_hash TEXT NOT NULL,
+        path TEXT,
+        size INTEGER,
+        file_type TEXT,
+        capture_time TEXT,
+        server_modified TEXT,
+        rev TEXT,
+        uploaded_at TEXT
+        );
+        CREATE UNIQUE INDEX files_content_hash ON indexed_files (content_hash);
+        INSERT OR IGNORE INTO indexed_files (name, content_hash, path, size, uploaded_at)
+        SELECT coalesce(name, ''), hash, path, size, uploaded_at FROM files
+        WHERE hash IS NOT NULL ORDER BY rowid;
+        DROP TABLE files;
+        ALTER TABLE indexed_files RENAME TO files;",
+    )?;
+    Ok(())
+}
+
+/// Version 2: upload sessions started but not committed yet, for the next run to resume.
+fn add_upload_sessions(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE upload_sessions (
+        session_id TEXT PRIMARY KEY,
+        path TEXT NOT NULL,
+        file_size INTEGER NOT NULL,
+        complete_up_to INTEGER NOT NULL,
+        uploaded_blocks TEXT NOT NULL,
+        started_at INTEGER NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+/// Version 3: what scanning found in each local file, so unchanged files aren't read again.
+fn add_scan_cache(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE scan_cache (
+        path TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        mtime INTEGER NOT NULL,
+        inode INTEGER NOT NULL,
+        timezone TEXT NOT NULL,
+        capture_time TEXT NOT NULL,
+        date_source TEXT NOT NULL,
+        extension TEXT NOT NULL,
+        model TEXT,
+        content_hash TEXT NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+/// Version 4: where the last listing of Dropbox ended, for `db sync` to continue from.
+fn add_list_cursor(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("CREATE TABLE list_cursor (source TEXT NOT NULL, cursor TEXT NOT NULL);")?;
+    Ok(())
+}
+
+/// Version 5: the mtime and inode of the file a session uploads, so that another file of the
+/// same size put at the same path isn't resumed into it. Older rows never match.
+fn add_session_stamps(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE upload_sessions ADD COLUMN mtime INTEGER;
+        ALTER TABLE upload_sessions ADD COLUMN inode INTEGER;",
+    )?;
+    Ok(())
+}
+
+/// Version 6: the other paths content indexed in `files` was seen at, for `db sync` to move a
+/// row to when its path goes.
+fn add_file_copies(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE file_copies (
+        path TEXT PRIMARY KEY COLLATE NOCASE,
+        content_hash TEXT NOT NULL
+        );",
     )?;
     Ok(())
 }
 
+fn add_missing_columns(tx: &Transaction, table: &str, columns: &[(&str, &str)]) -> Result<()> {
+    let existing = {
+        let mut stmt = tx.prepare(&format!("PRAGMA table_info({});", table))?;
+        let existing = stmt
+            .query_map(NO_PARAMS, |row| row.get::<_, String>(1))?
+            .collect::<SqResult<Vec<String>>>()?;
+        existing
+    };
+    for (column, column_type) in columns {
+        if !existing.iter().any(|c| c == column) {
+            tx.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {};",
+                table, column, column_type
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remembers a file committed by the uploader so later runs skip it without a `reset-db`.
+pub fn record_upload(
+    conn: &Connection,
+    file: &RemoteFile,
+    file_type: FileType,
+    capture_time: &str,
+) -> Result<()> {
+    insert(
+        conn,
+        &FileData {
+            file_type: Some(file_type),
+            capture_time: Some(capture_time.to_string()),
+            uploaded_at: Some(Utc::now().to_rfc3339()),
+            ..FileData::from_remote(file)?
+        },
+    )
+}
+
 pub fn exist(con: &Connection, hash: String) -> Result<bool> {
-    let mut stmt = con.prepare("SELECT name FROM files WHERE hash = ?")?;
+    let mut stmt = con.prepare("SELECT name FROM files WHERE content_hash = ?")?;
     match stmt.exists(&[hash]) {
         SqResult::Ok(b) => Ok(b),
         SqResult::Err(e) => Err(anyhow::anyhow!(e)),
     }
 }
 
+/// Opens the index, upgrading its schema if it is from an earlier version.
 pub fn connection(path: &str) -> Result<Connection> {
-    let conn = Connection::open(&path)?;
+    let mut conn = Connection::open(path)?;
+    // Upload batches record their results concurrently.
+    conn.busy_timeout(Duration::from_secs(30))?;
+    migrate(&mut conn)?;
     Ok(conn)
 }
+
+/// Dropbox expires upload sessions after 7 days, so older ones are not worth resuming.
+const SESSION_MAX_AGE: i64 = 6 * 24 * 60 * 60;
+
+/// Upload sessions that have been started but not committed yet.
+pub struct SessionJournal {
+    conn: Mutex<Connection>,
+}
+
+impl SessionJournal {
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = connection(path)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// The latest session for `path`, if the file is still the one it was started for.
+    pub fn find(&self, path: &str, stamp: FileStamp) -> Result<Option<Resume>> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM upload_sessions WHERE started_at < ?1;",
+            params![Utc::now().timestamp() - SESSION_MAX_AGE],
+        )?;
+        let row = conn
+            .query_row(
+                "SELECT session_id, complete_up_to, uploaded_blocks FROM upload_sessions
+                WHERE path = ?1 AND file_size = ?2 AND mtime = ?3 AND inode = ?4
+                ORDER BY started_at DESC LIMIT 1;",
+                params![path, stamp.size, stamp.mtime, stamp.inode],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        match row {
+            Some((session_id, complete_up_to, uploaded_blocks)) => Ok(Some(Resume {
+                session_id,
+                start_offset: complete_up_to as u64,
+                uploaded_blocks: serde_json::from_str(&uploaded_blocks)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    pub fn start(&self, path: &str, stamp: FileStamp, session_id: &str) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO upload_sessions
+            (session_id, path, file_size, mtime, inode, complete_up_to, uploaded_blocks,
+            started_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, 0, '{}', ?6);",
+            params![
+                session_id,
+                path,
+                stamp.size,
+                stamp.mtime,
+                stamp.inode,
+                Utc::now().timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn update(
+        &self,
+        session_id: &str,
+        complete_up_to: u64,
+        uploaded_blocks: &HashMap<u64, u64>,
+    ) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE upload_sessions SET complete_up_to = ?1, uploaded_blocks = ?2
+            WHERE session_id = ?3;",
+            params![
+                complete_up_to as i64,
+                serde_json::to_string(uploaded_blocks)?,
+                session_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove(&self, session_id: &str) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "DELETE FROM upload_sessions WHERE session_id = ?1;",
+            params![session_id],
+        )?;
+        Ok(())
+    }
+}
+
+/// What identifies a file's content without reading it: if none of these changed, neither
+/// did the capture time or the hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: i64,
+    /// Nanoseconds since the Unix epoch.
+    pub mtime: i64,
+    pub inode: i64,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        Self::from_metadata(&fs::metadata(path)?)
+    }
+
+    pub fn from_metadata(metadata: &fs::Metadata) -> io::Result<Self> {
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as i64)
+            .unwrap_or(0);
+        Ok(Self {
+            size: metadata.len() as i64,
+            mtime,
+            inode: inode(metadata),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> i64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino() as i64
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> i64 {
+    0
+}
+
+/// What a scan read from a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedScan {
+    /// The capture time as `DATETIME_FORMAT`, in the zone it was scanned with.
+    pub datetime: String,
+    pub digest: String,
+    pub model: Option<String>,
+    pub date_source: DateSource,
+    pub extension: Extension,
+}
+
+/// Scan results of files that haven't changed since, so `upload` needn't read them again.
+pub struct ScanCache {
+    conn: Mutex<Connection>,
+    /// Ignore what is stored, but still store what is read.
+    rehash: bool,
+}
+
+impl ScanCache {
+    pub fn open(path: &str, rehash: bool) -> Result<Self> {
+        let conn = connection(path)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            rehash,
+        })
+    }
+
+    /// The stored result for `path` if it was scanned as `stamp` with the same `timezone`,
+    /// which decides the capture time of files without a recorded offset.
+    pub fn get(&self, path: &str, stamp: FileStamp, timezone: &str) -> Result<Option<CachedScan>> {
+        if self.rehash {
+            return Ok(None);
+        }
+        let row = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT capture_time, content_hash, model, date_source, extension FROM scan_cache
+                WHERE path = ?1 AND size = ?2 AND mtime = ?3 AND inode = ?4 AND timezone = ?5;",
+                params![path, stamp.size, stamp.mtime, stamp.inode, timezone],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+        match row {
+            Some((datetime, digest, model, date_source, extension)) => Ok(Some(CachedScan {
+                datetime,
+                digest,
+                model,
+                date_source: date_source.parse()?,
+                extension: extension.parse()?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(
+        &self,
+        path: &str,
+        stamp: FileStamp,
+        timezone: &str,
+        scanned: &CachedScan,
+    ) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO scan_cache
+            (path, size, mtime, inode, timezone, capture_time, date_source, extension, model,
+            content_hash)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10);",
+            params![
+                path,
+                stamp.size,
+                stamp.mtime,
+                stamp.inode,
+                timezone,
+                scanned.datetime,
+                scanned.date_source.to_string(),
+                scanned.extension.remote_extension(),
+                scanned.model,
+                scanned.digest
+            ],
+        )?;
+        Ok(())
+    }
+}
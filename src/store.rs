@@ -0,0 +1,252 @@
+use crate::digest::dpx_digest;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// A file on the remote side.
+#[derive(Debug, Clone)]
+pub struct RemoteFile {
+    pub name: String,
+    pub path_display: String,
+    pub content_hash: Option<String>,
+    pub size: u64,
+    /// RFC 3339, as Dropbox reports it.
+    pub server_modified: Option<String>,
+    pub rev: Option<String>,
+}
+
+/// One page of a (possibly recursive) folder listing.
+#[derive(Debug)]
+pub struct ListPage {
+    pub entries: Vec<RemoteFile>,
+    /// Paths of files and folders removed since the cursor; only continued listings have any.
+    pub deleted: Vec<String>,
+    pub cursor: String,
+    pub has_more: bool,
+}
+
+/// The store no longer accepts appends to an upload session: it expired, was closed or is at
+/// another offset. The upload has to start over in a new session.
+#[derive(Debug, Error)]
+#[error("upload session lookup failed: {session_id}: {message}")]
+pub struct SessionLookupError {
+    pub session_id: String,
+    pub message: String,
+}
+
+/// Commit request for an upload session whose blocks have all been appended.
+#[derive(Debug, Clone)]
+pub struct FinishEntry {
+    pub session_id: String,
+    pub offset: u64,
+    pub path: String,
+    /// Replace a file already at `path` instead of failing with a conflict.
+    pub overwrite: bool,
+}
+
+pub type FinishEntryResult = std::result::Result<RemoteFile, String>;
+
+#[derive(Debug)]
+pub enum FinishBatchStatus {
+    /// The batch is still being committed; poll again with this job id.
+    InProgress(String),
+    /// Per-entry results, in the same order as the submitted entries.
+    Complete(Vec<FinishEntryResult>),
+}
+
+/// The operations the uploader and the index builder need from a storage backend.
+pub trait RemoteStore: Send + Sync {
+    fn list_folder(&self, path: &str, recursive: bool) -> Result<ListPage>;
+    /// The page after `cursor`, or the changes since it once the listing has ended. A store
+    /// that keeps no changes returns an error rather than an empty page.
+    fn list_folder_continue(&self, cursor: &str) -> Result<ListPage>;
+    fn upload_session_start(&self) -> Result<String>;
+    fn upload_session_append(
+        &self,
+        session_id: &str,
+        offset: u64,
+        data: &[u8],
+        close: bool,
+    ) -> Result<()>;
+    fn finish_batch(&self, entries: &[FinishEntry]) -> Result<FinishBatchStatus>;
+    fn finish_batch_check(&self, async_job_id: &str) -> Result<FinishBatchStatus>;
+    fn get_metadata(&self, path: &str) -> Result<Option<RemoteFile>>;
+    /// Content of the file at `path`, or `None` if there is none.
+    fn download(&self, path: &str) -> Result<Option<Vec<u8>>>;
+}
+
+const SESSION_DIR: &str = ".sessions";
+
+/// Stores files under a local directory, laid out the same way they would be on Dropbox.
+pub struct LocalStore {
+    root: PathBuf,
+    session_count: AtomicU64,
+}
+
+impl LocalStore {
+    pub fn new(root: &Path) -> Result<Self> {
+        fs::create_dir_all(root.join(SESSION_DIR))
+            .with_context(|| format!("failed to create local store: {:?}", root))?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            session_count: AtomicU64::new(0),
+        })
+    }
+
+    /// Only plain names are allowed, so no remote path reaches outside the root.
+    fn local_path(&self, remote_path: &str) -> Result<PathBuf> {
+        let relative = Path::new(remote_path.trim_start_matches('/'));
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return Err(anyhow::anyhow!("invalid remote path: {:?}", remote_path));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    fn session_path(&self, session_id: &str) -> PathBuf {
+        self.root.join(SESSION_DIR).join(session_id)
+    }
+
+    fn remote_file(&self, path: &Path) -> Result<RemoteFile> {
+        let relative = path.strip_prefix(&self.root)?;
+        let name = relative
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or(anyhow::anyhow!("invalid file name: {:?}", path))?
+            .to_string();
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let modified: DateTime<Utc> = metadata.modified()?.into();
+        Ok(RemoteFile {
+            name,
+            path_display: format!("/{}", relative.display()),
+            content_hash: Some(dpx_digest(&mut file)?),
+            size: metadata.len(),
+            server_modified: Some(modified.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+            // Local files have no revisions.
+            rev: None,
+        })
+    }
+
+    fn walk(&self, dir: &Path, recursive: bool, entries: &mut Vec<RemoteFile>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry_path = entry?.path();
+            if entry_path == self.root.join(SESSION_DIR) {
+                continue;
+            }
+            if entry_path.is_dir() {
+                if recursive {
+                    self.walk(&entry_path, recursive, entries)?;
+                }
+            } else {
+                entries.push(self.remote_file(&entry_path)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self, entry: &FinishEntry) -> FinishEntryResult {
+        let session_path = self.session_path(&entry.session_id);
+        let len = fs::metadata(&session_path)
+            .map_err(|e| format!("lookup failed: {}: {}", entry.session_id, e))?
+            .len();
+        if len != entry.offset {
+            return Err(format!(
+                "incorrect offset: {}: expected {}, got {}",
+                entry.session_id, len, entry.offset
+            ));
+        }
+        let dest = self.local_path(&entry.path).map_err(|e| format!("{}", e))?;
+        if dest.exists() && !entry.overwrite {
+            return Err(format!("path conflict: {}", entry.path));
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{}", e))?;
+        }
+        fs::rename(&session_path, &dest).map_err(|e| format!("{}", e))?;
+        self.remote_file(&dest).map_err(|e| format!("{}", e))
+    }
+}
+
+impl RemoteStore for LocalStore {
+    fn list_folder(&self, path: &str, recursive: bool) -> Result<ListPage> {
+        let mut entries = Vec::new();
+        self.walk(&self.local_path(path)?, recursive, &mut entries)?;
+        Ok(ListPage {
+            entries,
+            deleted: Vec::new(),
+            cursor: String::new(),
+            has_more: false,
+        })
+    }
+
+    /// `list_folder` returns everything at once, and no changes are kept to continue from.
+    fn list_folder_continue(&self, _cursor: &str) -> Result<ListPage> {
+        Err(anyhow::anyhow!(
+            "incremental sync not supported for local store"
+        ))
+    }
+
+    fn upload_session_start(&self) -> Result<String> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let session_id = format!(
+            "local-{}-{}",
+            nanos,
+            self.session_count.fetch_add(1, SeqCst)
+        );
+        File::create(self.session_path(&session_id))?;
+        Ok(session_id)
+    }
+
+    fn upload_session_append(
+        &self,
+        session_id: &str,
+        offset: u64,
+        data: &[u8],
+        _close: bool,
+    ) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(self.session_path(session_id))
+            .map_err(|e| SessionLookupError {
+                session_id: session_id.to_string(),
+                message: format!("{}", e),
+            })?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    fn finish_batch(&self, entries: &[FinishEntry]) -> Result<FinishBatchStatus> {
+        Ok(FinishBatchStatus::Complete(
+            entries.iter().map(|entry| self.finish(entry)).collect(),
+        ))
+    }
+
+    fn finish_batch_check(&self, async_job_id: &str) -> Result<FinishBatchStatus> {
+        Err(anyhow::anyhow!("unknown async job: {}", async_job_id))
+    }
+
+    fn get_metadata(&self, path: &str) -> Result<Option<RemoteFile>> {
+        let local_path = self.local_path(path)?;
+        if !local_path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(self.remote_file(&local_path)?))
+    }
+
+    fn download(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.local_path(path)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use chrono::Utc;
+use common::{temp_dir, MockDropbox};
+use my_dropbox_controller::auth::{Authenticator, Credentials};
+use my_dropbox_controller::dropbox::DropboxStore;
+use my_dropbox_controller::store::RemoteStore;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+fn write_credentials(path: &Path, access_token: &str, expires_at: i64) {
+    let credentials = Credentials {
+        app_key: "app-key".to_string(),
+        refresh_token: "refresh".to_string(),
+        access_token: Some(access_token.to_string()),
+        expires_at: Some(expires_at),
+    };
+    fs::write(path, serde_json::to_string(&credentials).unwrap()).unwrap();
+}
+
+fn saved_credentials(path: &Path) -> Credentials {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn expired_access_token_is_refreshed_and_saved() {
+    let server = MockDropbox::start();
+    server.set_refresh_token("refresh");
+    server.set_access_token("unused");
+    let path = temp_dir("auth-expired").join("credentials.json");
+    write_credentials(&path, "expired", Utc::now().timestamp() - 60);
+    let auth = Authenticator::load(&path)
+        .unwrap()
+        .with_base_url(server.url());
+    let store = DropboxStore::with_base_url(Arc::new(auth), server.url());
+
+    store.list_folder("", false).unwrap();
+
+    let credentials = saved_credentials(&path);
+    assert_eq!(credentials.access_token, Some("access-1".to_string()));
+    assert!(credentials.expires_at.unwrap() > Utc::now().timestamp());
+    assert_eq!(server.calls("/oauth2/token"), 1);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn rejected_access_token_is_refreshed_once() {
+    let server = MockDropbox::start();
+    server.set_refresh_token("refresh");
+    server.set_access_token("current");
+    let path = temp_dir("auth-rejected").join("credentials.json");
+    write_credentials(&path, "revoked", Utc::now().timestamp() + 3600);
+    let auth = Authenticator::load(&path)
+        .unwrap()
+        .with_base_url(server.url());
+    let store = DropboxStore::with_base_url(Arc::new(auth), server.url());
+
+    store.list_folder("", false).unwrap();
+    store.list_folder("", false).unwrap();
+
+    assert_eq!(server.calls("/oauth2/token"), 1);
+    assert_eq!(server.calls("files/list_folder"), 3);
+}
@@ -0,0 +1,739 @@
+//! In-process stand-in for the part of the Dropbox v2 API this crate talks to.
+#![allow(dead_code)]
+
+use dropbox_content_hasher::DropboxContentHasher;
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Tag};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Default)]
+struct State {
+    files: BTreeMap<String, Vec<u8>>,
+    /// Paths in the order they were added, changed or deleted, for continued listings.
+    changes: Vec<String>,
+    sessions: BTreeMap<String, Vec<u8>>,
+    jobs: BTreeMap<String, (u32, Value)>,
+    next_id: u64,
+    calls: Vec<String>,
+    page_size: usize,
+    pending_polls: u32,
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    rate_limits: BTreeMap<String, u32>,
+}
+
+impl State {
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}{}", prefix, self.next_id)
+    }
+}
+
+pub struct MockDropbox {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+struct Request {
+    function: String,
+    headers: BTreeMap<String, String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    code: u16,
+    body: String,
+    /// Set for content-download endpoints, whose body is the file itself.
+    download: Option<Vec<u8>>,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self {
+            code: 200,
+            body: body.to_string(),
+            download: None,
+        }
+    }
+
+    fn download(result: Value, data: &[u8]) -> Self {
+        Self {
+            code: 200,
+            body: result.to_string(),
+            download: Some(data.to_vec()),
+        }
+    }
+
+    fn route_error(summary: &str, error: Value) -> Self {
+        Self {
+            code: 409,
+            body: json!({ "error_summary": summary, "error": error }).to_string(),
+            download: None,
+        }
+    }
+
+    fn bad_request(message: &str) -> Self {
+        Self {
+            code: 400,
+            body: message.to_string(),
+            download: None,
+        }
+    }
+}
+
+impl MockDropbox {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State {
+            page_size: 1000,
+            ..State::default()
+        }));
+        let cloned = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let state = cloned.clone();
+                if let Ok(stream) = stream {
+                    thread::spawn(move || handle(stream, state));
+                }
+            }
+        });
+        Self { url, state }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Entries returned per list_folder / list_folder/continue call.
+    pub fn set_page_size(&self, page_size: usize) {
+        self.state.lock().unwrap().page_size = page_size;
+    }
+
+    /// How many times finish_batch/check answers `in_progress` before completing.
+    pub fn set_pending_polls(&self, pending_polls: u32) {
+        self.state.lock().unwrap().pending_polls = pending_polls;
+    }
+
+    /// Only accept this bearer token instead of any token.
+    pub fn set_access_token(&self, access_token: &str) {
+        self.state.lock().unwrap().access_token = Some(access_token.to_string());
+    }
+
+    /// Hand out fresh access tokens from `oauth2/token` for this refresh token.
+    pub fn set_refresh_token(&self, refresh_token: &str) {
+        self.state.lock().unwrap().refresh_token = Some(refresh_token.to_string());
+    }
+
+    /// Answer the next `times` calls of `function` with 429 too_many_write_operations.
+    pub fn set_rate_limited(&self, function: &str, times: u32) {
+        self.state
+            .lock()
+            .unwrap()
+            .rate_limits
+            .insert(function.to_string(), times);
+    }
+
+    pub fn put_file(&self, path: &str, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(path.to_string(), data.to_vec());
+        state.changes.push(path.to_string());
+    }
+
+    /// Deletes the file at `path`, or every file under it if it is a folder.
+    pub fn delete(&self, path: &str) {
+        let mut state = self.state.lock().unwrap();
+        let folder = format!("{}/", path);
+        state
+            .files
+            .retain(|file, _| file != path && !file.starts_with(&folder));
+        state.changes.push(path.to_string());
+    }
+
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().files.get(path).cloned()
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.state.lock().unwrap().files.keys().cloned().collect()
+    }
+
+    /// How many times `function`, e.g. `files/upload_session/start`, has been called.
+    pub fn calls(&self, function: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .iter()
+            .filter(|call| *call == function)
+            .count()
+    }
+}
+
+pub fn content_hash(data: &[u8]) -> String {
+    format!(
+        "{:x}",
+        DropboxContentHasher::hash_reader(&mut &data[..]).unwrap()
+    )
+}
+
+/// A minimal JPEG whose APP1 segment holds the given ASCII EXIF fields.
+pub fn jpeg_with_exif(fields: &[(Tag, &str)]) -> Vec<u8> {
+    jpeg_with_fields(
+        fields
+            .iter()
+            .map(|(tag, value)| Field {
+                tag: *tag,
+                ifd_num: In::PRIMARY,
+                value: exif::Value::Ascii(vec![value.as_bytes().to_vec()]),
+            })
+            .collect(),
+    )
+}
+
+pub fn jpeg_with_fields(fields: Vec<Field>) -> Vec<u8> {
+    let tiff = tiff_with_fields(fields);
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+    jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    jpeg.extend_from_slice(b"Exif\0\0");
+    jpeg.extend_from_slice(&tiff);
+    jpeg.extend_from_slice(&[0xff, 0xd9]);
+    jpeg
+}
+
+/// The EXIF fields as a bare TIFF structure.
+pub fn tiff_with_fields(fields: Vec<Field>) -> Vec<u8> {
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, false).unwrap();
+    tiff.into_inner()
+}
+
+/// A CR3 whose Canon `uuid` box holds `CMT1` with the camera model and `CMT2` with the
+/// ASCII Exif IFD fields, each stored as its own TIFF.
+pub fn cr3_with_exif(model: &str, fields: &[(Tag, &str)]) -> Vec<u8> {
+    let ifd0_field = |tag: Tag, value: &str| Field {
+        tag: Tag(Context::Tiff, tag.number()),
+        ifd_num: In::PRIMARY,
+        value: exif::Value::Ascii(vec![value.as_bytes().to_vec()]),
+    };
+    let cmt1 = tiff_with_fields(vec![ifd0_field(Tag::Model, model)]);
+    let cmt2 = tiff_with_fields(
+        fields
+            .iter()
+            .map(|(tag, value)| ifd0_field(*tag, value))
+            .collect(),
+    );
+    let mut canon = vec![
+        0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a,
+        0x48,
+    ];
+    canon.extend(mp4_box(b"CMT1", &cmt1));
+    canon.extend(mp4_box(b"CMT2", &cmt2));
+    let mut file = mp4_box(b"ftyp", b"crx \0\0\0\x01crx isom");
+    file.extend(mp4_box(b"moov", &mp4_box(b"uuid", &canon)));
+    file
+}
+
+/// A minimal HEIF whose `Exif` item, stored in `idat`, holds the given ASCII EXIF fields.
+pub fn heif_with_exif(fields: &[(Tag, &str)]) -> Vec<u8> {
+    let mut exif = vec![0u8; 4];
+    exif.extend(tiff_with_fields(
+        fields
+            .iter()
+            .map(|(tag, value)| Field {
+                tag: *tag,
+                ifd_num: In::PRIMARY,
+                value: exif::Value::Ascii(vec![value.as_bytes().to_vec()]),
+            })
+            .collect(),
+    ));
+    let mut infe = vec![2u8, 0, 0, 0, 0, 1, 0, 0];
+    infe.extend_from_slice(b"Exif\0");
+    let mut iinf = vec![0u8, 0, 0, 0, 0, 1];
+    iinf.extend(mp4_box(b"infe", &infe));
+    // Version 1, 4-byte offsets and lengths, one item built from `idat` with one extent.
+    let mut iloc = vec![1u8, 0, 0, 0, 0x44, 0x00, 0, 1, 0, 1, 0, 1, 0, 0, 0, 1];
+    iloc.extend_from_slice(&0u32.to_be_bytes());
+    iloc.extend_from_slice(&(exif.len() as u32).to_be_bytes());
+    let mut meta = vec![0u8; 4];
+    meta.extend(mp4_box(b"iinf", &iinf));
+    meta.extend(mp4_box(b"iloc", &iloc));
+    meta.extend(mp4_box(b"idat", &exif));
+    let mut file = mp4_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+    file.extend(mp4_box(b"meta", &meta));
+    file
+}
+
+pub fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut boxed = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+    boxed.extend_from_slice(kind);
+    boxed.extend_from_slice(content);
+    boxed
+}
+
+/// A version 0 `mvhd` with the given raw creation time, a 1000 timescale and `duration` in
+/// seconds.
+pub fn mvhd(creation_time: u32, duration: u32) -> Vec<u8> {
+    let mut content = vec![0u8; 4];
+    content.extend_from_slice(&creation_time.to_be_bytes());
+    // A modification time far from any creation time used in tests.
+    content.extend_from_slice(&1u32.to_be_bytes());
+    content.extend_from_slice(&1000u32.to_be_bytes());
+    content.extend_from_slice(&(duration * 1000).to_be_bytes());
+    content.resize(100, 0);
+    mp4_box(b"mvhd", &content)
+}
+
+/// A `trak` whose version 0 `tkhd` gives the track size.
+pub fn video_track(width: u16, height: u16) -> Vec<u8> {
+    let mut content = vec![0u8; 76];
+    content.extend_from_slice(&((width as u32) << 16).to_be_bytes());
+    content.extend_from_slice(&((height as u32) << 16).to_be_bytes());
+    mp4_box(b"trak", &mp4_box(b"tkhd", &content))
+}
+
+/// A movie with `ftyp`, an empty `mdat` and `moov` holding `moov_children`.
+pub fn mp4_with(moov_children: &[Vec<u8>]) -> Vec<u8> {
+    let mut file = mp4_box(b"ftyp", b"isom\0\0\0\0isommp41");
+    file.extend(mp4_box(b"mdat", &[]));
+    file.extend(mp4_box(b"moov", &moov_children.concat()));
+    file
+}
+
+/// QuickTime `meta` with one `mdta` key and its UTF-8 value.
+pub fn quicktime_meta(key: &str, value: &str) -> Vec<u8> {
+    let mut keys = vec![0u8, 0, 0, 0, 0, 0, 0, 1];
+    keys.extend_from_slice(&((key.len() + 8) as u32).to_be_bytes());
+    keys.extend_from_slice(b"mdta");
+    keys.extend_from_slice(key.as_bytes());
+    let mut data = vec![0u8, 0, 0, 1, 0, 0, 0, 0];
+    data.extend_from_slice(value.as_bytes());
+    let item = mp4_box(&1u32.to_be_bytes(), &mp4_box(b"data", &data));
+    let mut hdlr = vec![0u8; 8];
+    hdlr.extend_from_slice(b"mdta");
+    hdlr.resize(25, 0);
+    mp4_box(
+        b"meta",
+        &[
+            mp4_box(b"hdlr", &hdlr),
+            mp4_box(b"keys", &keys),
+            mp4_box(b"ilst", &item),
+        ]
+        .concat(),
+    )
+}
+
+/// `udta` with a QuickTime style `©day` text atom.
+pub fn user_data_day(value: &str) -> Vec<u8> {
+    let mut text = (value.len() as u16).to_be_bytes().to_vec();
+    text.extend_from_slice(&[0x55, 0xc4]);
+    text.extend_from_slice(value.as_bytes());
+    mp4_box(b"udta", &mp4_box(b"\xa9day", &text))
+}
+
+pub fn riff_chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+pub fn riff_list(list_type: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = list_type.to_vec();
+    data.extend(children.concat());
+    riff_chunk(b"LIST", &data)
+}
+
+/// An AVI with `hdrl_children` after `avih`, a `movi` list with one frame, and `info` as
+/// the `INFO` list if given.
+pub fn avi_with(hdrl_children: &[Vec<u8>], info: Option<&[Vec<u8>]>) -> Vec<u8> {
+    let mut hdrl = vec![riff_chunk(b"avih", &[0u8; 56])];
+    hdrl.extend_from_slice(hdrl_children);
+    let mut data = b"AVI ".to_vec();
+    data.extend(riff_list(b"hdrl", &hdrl));
+    // A frame that looks like a date chunk, to check `movi` is skipped.
+    data.extend(riff_list(
+        b"movi",
+        &[riff_chunk(b"IDIT", b"MON JAN 01 00:00:00 2001\n\0")],
+    ));
+    if let Some(info) = info {
+        data.extend(riff_list(b"INFO", info));
+    }
+    let mut riff = b"RIFF".to_vec();
+    riff.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    riff.extend(data);
+    riff
+}
+
+/// A fresh, empty directory under the system temp dir.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "my-dropbox-controller-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn handle(stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut reader = BufReader::new(stream);
+    let request = match read_request(&mut reader) {
+        Some(request) => request,
+        None => return,
+    };
+    let mut state = state.lock().unwrap();
+    state.calls.push(request.function.clone());
+    let authorized = match (&state.access_token, request.headers.get("authorization")) {
+        (Some(token), Some(header)) => *header == format!("Bearer {}", token),
+        (None, header) => header.is_some(),
+        (_, None) => false,
+    };
+    let rate_limited = match state.rate_limits.get_mut(&request.function) {
+        Some(times) if *times > 0 => {
+            *times -= 1;
+            true
+        }
+        _ => false,
+    };
+    let response = if request.function == "/oauth2/token" {
+        token(&mut state, &request)
+    } else if rate_limited {
+        Response {
+            code: 429,
+            body: json!({
+                "error_summary": "too_many_write_operations/",
+                "error": { "reason": { ".tag": "too_many_write_operations" }, "retry_after": 0 }
+            })
+            .to_string(),
+            download: None,
+        }
+    } else if authorized {
+        route(&mut state, &request)
+    } else {
+        Response {
+            code: 401,
+            body: json!({
+                "error_summary": "invalid_access_token/",
+                "error": { ".tag": "invalid_access_token" }
+            })
+            .to_string(),
+            download: None,
+        }
+    };
+    drop(state);
+    let status = match response.code {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        409 => "Conflict",
+        429 => "Too Many Requests",
+        _ => "Error",
+    };
+    let mut stream = reader.into_inner();
+    let retry_after = if response.code == 429 {
+        "Retry-After: 0\r\n"
+    } else {
+        ""
+    };
+    let _ = match &response.download {
+        Some(data) => write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nDropbox-API-Result: {}\r\nConnection: close\r\n\r\n",
+            response.code,
+            status,
+            data.len(),
+            response.body
+        )
+        .and_then(|_| stream.write_all(data)),
+        None => write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+            response.code,
+            status,
+            response.body.len(),
+            retry_after,
+            response.body
+        ),
+    };
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let path = line.split_whitespace().nth(1)?;
+    let function = path.trim_start_matches("/2/").to_string();
+    let mut headers = BTreeMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_at(line.find(':')?);
+        headers.insert(name.to_lowercase(), value[1..].trim().to_string());
+    }
+    let len = headers
+        .get("content-length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        function,
+        headers,
+        body,
+    })
+}
+
+fn arg(request: &Request) -> Value {
+    match request.headers.get("dropbox-api-arg") {
+        Some(arg) => serde_json::from_str(arg).unwrap_or(Value::Null),
+        None => serde_json::from_slice(&request.body).unwrap_or(Value::Null),
+    }
+}
+
+fn file_metadata(path: &str, data: &[u8]) -> Value {
+    json!({
+        "name": path.rsplit('/').next().unwrap_or(path),
+        "id": format!("id:{}", content_hash(path.as_bytes())),
+        "client_modified": "2021-01-01T00:00:00Z",
+        "server_modified": "2021-01-01T00:00:00Z",
+        "rev": "015c0b9c1c0d4d50000000001",
+        "size": data.len(),
+        "path_lower": path.to_lowercase(),
+        "path_display": path,
+        "content_hash": content_hash(data),
+    })
+}
+
+fn tagged(tag: &str, mut value: Value) -> Value {
+    value[".tag"] = json!(tag);
+    value
+}
+
+fn list_page(state: &State, prefix: &str, offset: usize) -> Value {
+    let matching: Vec<(&String, &Vec<u8>)> = state
+        .files
+        .iter()
+        .filter(|(path, _)| prefix.is_empty() || path.starts_with(&format!("{}/", prefix)))
+        .collect();
+    let end = (offset + state.page_size).min(matching.len());
+    let entries: Vec<Value> = matching[offset..end]
+        .iter()
+        .map(|(path, data)| tagged("file", file_metadata(path, data)))
+        .collect();
+    // The last page hands over to the change log.
+    let cursor = if end < matching.len() {
+        format!("{}|{}", end, prefix)
+    } else {
+        format!("changes|{}|{}", state.changes.len(), prefix)
+    };
+    json!({
+        "entries": entries,
+        "cursor": cursor,
+        "has_more": end < matching.len(),
+    })
+}
+
+/// The current state of each path changed since `offset` in the change log.
+fn change_page(state: &State, prefix: &str, offset: usize) -> Value {
+    let end = (offset + state.page_size).min(state.changes.len());
+    let entries: Vec<Value> = state.changes[offset.min(end)..end]
+        .iter()
+        .filter(|path| prefix.is_empty() || path.starts_with(&format!("{}/", prefix)))
+        .map(|path| match state.files.get(path) {
+            Some(data) => tagged("file", file_metadata(path, data)),
+            None => json!({
+                ".tag": "deleted",
+                "name": path.rsplit('/').next().unwrap_or(""),
+                "path_lower": path.to_lowercase(),
+                "path_display": path,
+            }),
+        })
+        .collect();
+    json!({
+        "entries": entries,
+        "cursor": format!("changes|{}|{}", end, prefix),
+        "has_more": end < state.changes.len(),
+    })
+}
+
+fn token(state: &mut State, request: &Request) -> Response {
+    let form: BTreeMap<&str, &str> = std::str::from_utf8(&request.body)
+        .unwrap_or("")
+        .split('&')
+        .filter_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            Some((kv.next()?, kv.next()?))
+        })
+        .collect();
+    match (form.get("grant_type"), &state.refresh_token) {
+        (Some(&"refresh_token"), Some(refresh_token))
+            if form.get("refresh_token") == Some(&refresh_token.as_str()) =>
+        {
+            let access_token = state.next_id("access-");
+            state.access_token = Some(access_token.clone());
+            Response::ok(json!({
+                "access_token": access_token,
+                "token_type": "bearer",
+                "expires_in": 14400,
+            }))
+        }
+        _ => Response::bad_request(&json!({ "error": "invalid_grant" }).to_string()),
+    }
+}
+
+fn route(state: &mut State, request: &Request) -> Response {
+    let arg = arg(request);
+    match request.function.as_str() {
+        "files/list_folder" => {
+            let prefix = arg["path"].as_str().unwrap_or("").to_string();
+            Response::ok(list_page(state, &prefix, 0))
+        }
+        "files/list_folder/continue" => {
+            let cursor = arg["cursor"].as_str().unwrap_or("");
+            if let Some(rest) = cursor.strip_prefix("changes|") {
+                let mut parts = rest.splitn(2, '|');
+                return match (
+                    parts.next().and_then(|offset| offset.parse().ok()),
+                    parts.next(),
+                ) {
+                    (Some(offset), Some(prefix)) => {
+                        Response::ok(change_page(state, prefix, offset))
+                    }
+                    _ => Response::route_error("reset/", json!({ ".tag": "reset" })),
+                };
+            }
+            let mut parts = cursor.splitn(2, '|');
+            match (
+                parts.next().and_then(|offset| offset.parse().ok()),
+                parts.next(),
+            ) {
+                (Some(offset), Some(prefix)) => Response::ok(list_page(state, prefix, offset)),
+                _ => Response::route_error("reset/", json!({ ".tag": "reset" })),
+            }
+        }
+        "files/upload_session/start" => {
+            let session_id = state.next_id("session-");
+            state
+                .sessions
+                .insert(session_id.clone(), request.body.clone());
+            Response::ok(json!({ "session_id": session_id }))
+        }
+        "files/upload_session/append_v2" => {
+            let session_id = arg["cursor"]["session_id"].as_str().unwrap_or("");
+            let offset = arg["cursor"]["offset"].as_u64().unwrap_or(0) as usize;
+            match state.sessions.get_mut(session_id) {
+                Some(data) => {
+                    let end = offset + request.body.len();
+                    if data.len() < end {
+                        data.resize(end, 0);
+                    }
+                    data[offset..end].copy_from_slice(&request.body);
+                    Response::ok(Value::Null)
+                }
+                None => Response::route_error("not_found/", json!({ ".tag": "not_found" })),
+            }
+        }
+        "files/upload_session/finish_batch" => {
+            let mut entries = Vec::new();
+            for entry in arg["entries"].as_array().cloned().unwrap_or_default() {
+                let session_id = entry["cursor"]["session_id"].as_str().unwrap_or("");
+                let offset = entry["cursor"]["offset"].as_u64().unwrap_or(0);
+                let path = entry["commit"]["path"].as_str().unwrap_or("").to_string();
+                let result = match state.sessions.remove(session_id) {
+                    None => tagged(
+                        "failure",
+                        json!({ "failure": { ".tag": "lookup_failed", "lookup_failed": { ".tag": "not_found" } } }),
+                    ),
+                    Some(data) if data.len() as u64 != offset => tagged(
+                        "failure",
+                        json!({ "failure": { ".tag": "lookup_failed", "lookup_failed": {
+                            ".tag": "incorrect_offset", "correct_offset": data.len()
+                        } } }),
+                    ),
+                    Some(_)
+                        if state.files.contains_key(&path)
+                            && entry["commit"]["mode"][".tag"] != "overwrite" =>
+                    {
+                        tagged(
+                            "failure",
+                            json!({ "failure": { ".tag": "path", "path": {
+                                ".tag": "conflict", "conflict": { ".tag": "file" }
+                            } } }),
+                        )
+                    }
+                    Some(data) => {
+                        let metadata = file_metadata(&path, &data);
+                        state.changes.push(path.clone());
+                        state.files.insert(path, data);
+                        tagged("success", metadata)
+                    }
+                };
+                entries.push(result);
+            }
+            let async_job_id = state.next_id("job-");
+            let pending_polls = state.pending_polls;
+            state.jobs.insert(
+                async_job_id.clone(),
+                (
+                    pending_polls,
+                    json!({ ".tag": "complete", "entries": entries }),
+                ),
+            );
+            Response::ok(json!({ ".tag": "async_job_id", "async_job_id": async_job_id }))
+        }
+        "files/upload_session/finish_batch/check" => {
+            let async_job_id = arg["async_job_id"].as_str().unwrap_or("");
+            match state.jobs.get_mut(async_job_id) {
+                Some((pending, _)) if *pending > 0 => {
+                    *pending -= 1;
+                    Response::ok(json!({ ".tag": "in_progress" }))
+                }
+                Some((_, result)) => Response::ok(result.clone()),
+                None => Response::route_error(
+                    "invalid_async_job_id/",
+                    json!({ ".tag": "invalid_async_job_id" }),
+                ),
+            }
+        }
+        "files/get_metadata" => {
+            let path = arg["path"].as_str().unwrap_or("");
+            match state.files.get(path) {
+                Some(data) => Response::ok(tagged("file", file_metadata(path, data))),
+                None => Response::route_error(
+                    "path/not_found/",
+                    json!({ ".tag": "path", "path": { ".tag": "not_found" } }),
+                ),
+            }
+        }
+        "files/download" => {
+            let path = arg["path"].as_str().unwrap_or("");
+            match state.files.get(path) {
+                Some(data) => Response::download(file_metadata(path, data), data),
+                None => Response::route_error(
+                    "path/not_found/",
+                    json!({ ".tag": "path", "path": { ".tag": "not_found" } }),
+                ),
+            }
+        }
+        function => Response::bad_request(&format!("unknown endpoint: {}", function)),
+    }
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::temp_dir;
+use my_dropbox_controller::calc::NameDigest;
+use my_dropbox_controller::config::Config;
+use my_dropbox_controller::extension::Extension;
+use my_dropbox_controller::meta::DateSource;
+use std::fs;
+
+fn photo(name: &str, model: Option<&str>) -> NameDigest {
+    NameDigest {
+        digest: String::new(),
+        name: name.to_string(),
+        path: format!("/sd/DCIM/{}", name),
+        model: model.map(|model| model.to_string()),
+        date_source: DateSource::DateTimeOriginal,
+        extension: Extension::Jpeg,
+    }
+}
+
+#[test]
+fn default_config_keeps_datetime_names() {
+    let config = Config::default();
+    let file = photo("IMG_0001.JPG", None);
+
+    assert_eq!(
+        config
+            .remote_path("2024-05-01 12:34:56", 0, &file, "jpg")
+            .unwrap(),
+        "/カメラアップロード/2024-05-01 12:34:56.jpg"
+    );
+    assert_eq!(
+        config
+            .remote_path("2024-05-01 12:34:56", 2, &file, "jpg")
+            .unwrap(),
+        "/カメラアップロード/2024-05-01 12:34:56_2.jpg"
+    );
+}
+
+#[test]
+fn config_file_sets_template_and_subfolders() {
+    let dir = temp_dir("config");
+    let path = dir.join("config.toml");
+    fs::write(
+        &path,
+        r#"
+dest_root = "/Photos/"
+name_template = "%Y%m%d_%H%M%S_{model}_{name}{counter}"
+subfolders = "%Y/%m"
+"#,
+    )
+    .unwrap();
+
+    let config = Config::load(&path).unwrap();
+
+    assert_eq!(config.index, "my-dropbox.db3");
+    assert_eq!(config.remote_index, "/my-dropbox2.db3");
+    assert_eq!(
+        config
+            .remote_path(
+                "2024-05-01 12:34:56",
+                1,
+                &photo("IMG_0001.JPG", Some("ILCE-7M3 / A7")),
+                "jpg"
+            )
+            .unwrap(),
+        "/Photos/2024/05/20240501_123456_ILCE-7M3 _ A7_IMG_0001_1.jpg"
+    );
+    assert_eq!(
+        config
+            .remote_path("2024-05-01 12:34:56", 0, &photo("MOV.MP4", None), "mp4")
+            .unwrap(),
+        "/Photos/2024/05/20240501_123456_unknown_MOV.mp4"
+    );
+}
+
+#[test]
+fn missing_config_file_means_defaults_and_bad_templates_are_rejected() {
+    let dir = temp_dir("config-missing");
+    let config = Config::load(&dir.join("config.toml")).unwrap();
+    assert_eq!(config.dest_root, "/カメラアップロード");
+
+    let mut config = Config {
+        name_template: "%Y-%m-%d".to_string(),
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+    config.name_template = "%Y-%m-%d {name}".to_string();
+    assert!(config.validate().is_err());
+    config.name_template = "%Q{counter}".to_string();
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::content_hash;
+use my_dropbox_controller::digest::{digests, dpx_digest, sha_256_digest};
+use ring::digest::{digest, SHA256};
+use std::io::{Cursor, Read};
+
+#[test]
+fn one_pass_gives_both_hashes_and_rewinds() {
+    // Spans two Dropbox blocks and ends mid-buffer.
+    let data: Vec<u8> = (0..9 * 1024 * 1024 + 123)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let mut reader = Cursor::new(&data);
+
+    let both = digests(&mut reader).unwrap();
+
+    assert_eq!(both.content_hash, content_hash(&data));
+    assert_eq!(both.sha256.as_ref(), digest(&SHA256, &data).as_ref());
+    let mut first = [0u8; 4];
+    reader.read_exact(&mut first).unwrap();
+    assert_eq!(first, [0, 1, 2, 3]);
+    reader.set_position(0);
+    assert_eq!(dpx_digest(&mut reader).unwrap(), both.content_hash);
+    assert_eq!(
+        sha_256_digest(&mut reader).unwrap().as_ref(),
+        both.sha256.as_ref()
+    );
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::{content_hash, jpeg_with_fields, mp4_with, mvhd, temp_dir, video_track};
+use exif::{Field, In, Rational, Tag, Value};
+use my_dropbox_controller::config::Config;
+use my_dropbox_controller::inspect::inspect;
+use my_dropbox_controller::sqlite::connection;
+use rusqlite::params;
+use std::fs;
+
+fn ascii(tag: Tag, value: &str) -> Field {
+    Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![value.as_bytes().to_vec()]),
+    }
+}
+
+fn dms(tag: Tag, degrees: u32, minutes: u32, seconds: u32) -> Field {
+    Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value: Value::Rational(vec![
+            Rational::from((degrees, 1)),
+            Rational::from((minutes, 1)),
+            Rational::from((seconds, 1)),
+        ]),
+    }
+}
+
+#[test]
+fn inspect_reports_details_and_planned_names() {
+    let dir = temp_dir("inspect");
+    let photos = dir.join("photos");
+    fs::create_dir_all(photos.join("sub")).unwrap();
+    let jpeg = jpeg_with_fields(vec![
+        ascii(Tag::DateTimeOriginal, "2024:05:01 12:00:00"),
+        ascii(Tag::Make, "Canon"),
+        ascii(Tag::Model, "Canon EOS R6"),
+        ascii(Tag::GPSLatitudeRef, "N"),
+        dms(Tag::GPSLatitude, 35, 30, 0),
+        ascii(Tag::GPSLongitudeRef, "W"),
+        dms(Tag::GPSLongitude, 139, 45, 0),
+    ]);
+    fs::write(photos.join("a.jpg"), &jpeg).unwrap();
+    fs::write(photos.join("known.jpg"), b"known").unwrap();
+    // 2024-05-01 03:00:00 UTC, counted from 1904.
+    let movie = mp4_with(&[mvhd(3_797_377_200, 12), video_track(1920, 1080)]);
+    fs::write(photos.join("sub").join("b.mp4"), &movie).unwrap();
+    fs::write(dir.join("notes.txt"), b"notes").unwrap();
+    let db_path = dir.join("index.db3").display().to_string();
+    let conn = connection(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO files (name, content_hash) VALUES (?1, ?2);",
+        params!["known.jpg", content_hash(b"known")],
+    )
+    .unwrap();
+
+    let infos = inspect(
+        &[photos.clone(), dir.join("notes.txt")],
+        &db_path,
+        &Config::default(),
+    )
+    .unwrap();
+
+    let paths: Vec<_> = infos.iter().map(|info| info.path.as_str()).collect();
+    let path = |name: &str| dir.join(name).display().to_string();
+    assert_eq!(
+        paths,
+        vec![
+            path("photos/a.jpg"),
+            path("photos/known.jpg"),
+            path("photos/sub/b.mp4"),
+            path("notes.txt"),
+        ]
+    );
+
+    let photo = &infos[0];
+    assert_eq!(photo.kind, "Jpeg");
+    assert_eq!(
+        photo.capture_time.as_deref(),
+        Some("2024-05-01T12:00:00+09:00")
+    );
+    assert_eq!(photo.date_source.as_deref(), Some("DateTimeOriginal"));
+    assert_eq!(photo.timezone, "Asia/Tokyo");
+    assert_eq!(photo.details.model.as_deref(), Some("Canon EOS R6"));
+    let gps = photo.details.gps.as_ref().unwrap();
+    assert_eq!((gps.latitude, gps.longitude), (35.5, -139.75));
+    assert_eq!(
+        photo.content_hash.as_deref(),
+        Some(&content_hash(&jpeg)[..])
+    );
+    assert_eq!(photo.sha256.as_ref().map(|hash| hash.len()), Some(64));
+    assert_eq!(
+        photo.remote_name.as_deref(),
+        Some("/カメラアップロード/2024-05-01 12:00:00.jpg")
+    );
+
+    let known = &infos[1];
+    assert!(known.duplicate);
+    assert_eq!(known.remote_name, None);
+
+    let video = &infos[2];
+    assert_eq!(video.kind, "Mp4");
+    assert_eq!(video.date_source.as_deref(), Some("MP4 creation_time"));
+    assert_eq!(
+        (video.details.width, video.details.height),
+        (Some(1920), Some(1080))
+    );
+    assert_eq!(video.details.duration, Some(12.0));
+    assert_eq!(
+        video.remote_name.as_deref(),
+        Some("/カメラアップロード/2024-05-01 12:00:00.mp4")
+    );
+
+    let notes = &infos[3];
+    assert_eq!(notes.remote_name, None);
+    assert!(notes.error.is_some());
+
+    let json = serde_json::to_value(&infos).unwrap();
+    assert_eq!(json[0]["type"], "Jpeg");
+    assert_eq!(json[0]["model"], "Canon EOS R6");
+    assert_eq!(json[2]["width"], 1920);
+}
+
+#[test]
+fn inspect_leaves_a_missing_index_uncreated() {
+    let dir = temp_dir("inspect-no-index");
+    let jpeg = jpeg_with_fields(vec![ascii(Tag::DateTimeOriginal, "2024:05:01 12:00:00")]);
+    fs::write(dir.join("a.jpg"), &jpeg).unwrap();
+    let db_path = dir.join("index.db3");
+
+    let infos = inspect(
+        &[dir.join("a.jpg")],
+        &db_path.display().to_string(),
+        &Config::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        infos[0].remote_name.as_deref(),
+        Some("/カメラアップロード/2024-05-01 12:00:00.jpg")
+    );
+    assert!(!db_path.exists());
+}
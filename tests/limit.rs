@@ -0,0 +1,49 @@
+use my_dropbox_controller::limit::{parse_bytes, UploadLimiter};
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn in_flight_blocks_are_capped_across_threads() {
+    let limiter = Arc::new(UploadLimiter::new(Some(2), None));
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let limiter = limiter.clone();
+            let in_flight = in_flight.clone();
+            let peak = peak.clone();
+            thread::spawn(move || {
+                let _permit = limiter.acquire(1);
+                let now = in_flight.fetch_add(1, SeqCst) + 1;
+                peak.fetch_max(now, SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                in_flight.fetch_sub(1, SeqCst);
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(peak.load(SeqCst), 2);
+}
+
+#[test]
+fn bandwidth_is_paced() {
+    let limiter = UploadLimiter::new(None, Some(100_000));
+    let start = Instant::now();
+    for _ in 0..4 {
+        let _permit = limiter.acquire(10_000);
+    }
+    // The first block goes out immediately, the other three wait 0.1s each.
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[test]
+fn byte_sizes_accept_suffixes() {
+    assert_eq!(parse_bytes("1000").unwrap(), 1000);
+    assert_eq!(parse_bytes("512K").unwrap(), 512 * 1024);
+    assert_eq!(parse_bytes("2m").unwrap(), 2 * 1024 * 1024);
+    assert!(parse_bytes("2X").is_err());
+}
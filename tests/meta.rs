@@ -0,0 +1,362 @@
+mod common;
+
+use chrono::{FixedOffset, NaiveDate, TimeZone};
+use chrono_tz::Asia::Tokyo;
+use chrono_tz::Europe::Paris;
+use common::{
+    avi_with, cr3_with_exif, heif_with_exif, jpeg_with_exif, jpeg_with_fields, mp4_with, mvhd,
+    quicktime_meta, riff_chunk, temp_dir, tiff_with_fields, user_data_day,
+};
+use exif::{Field, In, Rational, Tag, Value};
+use filetime::FileTime;
+use my_dropbox_controller::extension::Extension;
+use my_dropbox_controller::meta::{
+    camera_model, datetime, datetime_and_model, file_name_datetime, get_datetime, get_mp4_datetime,
+    CaptureTime, DateSource,
+};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+fn read_datetime(name: &str, jpeg: &[u8], zone: chrono_tz::Tz) -> CaptureTime {
+    let path = temp_dir(name).join("photo.jpg");
+    fs::write(&path, jpeg).unwrap();
+    let file = File::open(&path).unwrap();
+    get_datetime(&mut BufReader::new(&file), zone).unwrap()
+}
+
+fn read_file_datetime(path: &Path, data: &[u8]) -> CaptureTime {
+    fs::write(path, data).unwrap();
+    let file = File::open(path).unwrap();
+    datetime(path, &mut BufReader::new(&file), &Extension::Jpeg, Tokyo).unwrap()
+}
+
+fn read_mp4_datetime(name: &str, mp4: &[u8], mtime: Option<u64>) -> CaptureTime {
+    let path = temp_dir(name).join("movie.mp4");
+    fs::write(&path, mp4).unwrap();
+    if let Some(mtime) = mtime {
+        filetime::set_file_mtime(&path, FileTime::from_unix_time(mtime as i64, 0)).unwrap();
+    }
+    let file = File::open(&path).unwrap();
+    get_mp4_datetime(&mut BufReader::new(&file), Tokyo).unwrap()
+}
+
+#[test]
+fn exif_offset_wins_over_default_zone() {
+    let jpeg = jpeg_with_exif(&[
+        (Tag::DateTimeOriginal, "2024:05:01 12:34:56"),
+        (Tag::OffsetTimeOriginal, "+02:00"),
+    ]);
+
+    assert_eq!(
+        read_datetime("offset", &jpeg, Tokyo).datetime.to_rfc3339(),
+        FixedOffset::east(2 * 3600)
+            .ymd(2024, 5, 1)
+            .and_hms(12, 34, 56)
+            .to_rfc3339()
+    );
+}
+
+#[test]
+fn offset_time_applies_without_the_specific_offset_tag() {
+    let jpeg = jpeg_with_exif(&[
+        (Tag::DateTimeOriginal, "2024:05:01 12:34:56"),
+        (Tag::OffsetTime, "-05:00"),
+    ]);
+
+    assert_eq!(
+        read_datetime("offset-time", &jpeg, Tokyo)
+            .datetime
+            .to_rfc3339(),
+        "2024-05-01T12:34:56-05:00"
+    );
+}
+
+#[test]
+fn default_zone_applies_without_exif_offset() {
+    let jpeg = jpeg_with_exif(&[(Tag::DateTime, "2024:01:15 08:00:00")]);
+
+    assert_eq!(
+        read_datetime("tokyo", &jpeg, Tokyo).datetime.to_rfc3339(),
+        "2024-01-15T08:00:00+09:00"
+    );
+    assert_eq!(
+        read_datetime("paris", &jpeg, Paris).datetime.to_rfc3339(),
+        "2024-01-15T08:00:00+01:00"
+    );
+}
+
+#[test]
+fn date_time_original_wins_over_edited_date_time() {
+    let jpeg = jpeg_with_exif(&[
+        (Tag::DateTime, "2024:06:01 09:00:00"),
+        (Tag::DateTimeDigitized, "2024:05:01 12:00:01"),
+        (Tag::DateTimeOriginal, "2024:05:01 12:00:00"),
+    ]);
+    let capture_time = read_datetime("original", &jpeg, Tokyo);
+    assert_eq!(capture_time.source, DateSource::DateTimeOriginal);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T12:00:00+09:00"
+    );
+
+    let jpeg = jpeg_with_exif(&[
+        (Tag::DateTime, "2024:06:01 09:00:00"),
+        (Tag::DateTimeDigitized, "2024:05:01 12:00:01"),
+    ]);
+    assert_eq!(
+        read_datetime("digitized", &jpeg, Tokyo).source,
+        DateSource::DateTimeDigitized
+    );
+}
+
+#[test]
+fn gps_time_is_converted_from_utc() {
+    let rational = |num| Rational { num, denom: 1 };
+    let jpeg = jpeg_with_fields(vec![
+        Field {
+            tag: Tag::GPSDateStamp,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![b"2024:05:01".to_vec()]),
+        },
+        Field {
+            tag: Tag::GPSTimeStamp,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(vec![rational(23), rational(30), rational(0)]),
+        },
+    ]);
+
+    let capture_time = read_datetime("gps", &jpeg, Tokyo);
+
+    assert_eq!(capture_time.source, DateSource::Gps);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-02T08:30:00+09:00"
+    );
+}
+
+#[test]
+fn file_name_and_mtime_are_the_last_resort() {
+    let dir = temp_dir("fallback");
+    let capture_time = read_file_datetime(&dir.join("IMG_20240501_123456.jpg"), b"not a jpeg");
+    assert_eq!(capture_time.source, DateSource::FileName);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T12:34:56+09:00"
+    );
+
+    let capture_time = read_file_datetime(&dir.join("IMG_0001.jpg"), b"not a jpeg");
+    assert_eq!(capture_time.source, DateSource::Mtime);
+}
+
+#[test]
+fn file_name_patterns() {
+    let at = |h, m, s| NaiveDate::from_ymd(2024, 5, 1).and_hms(h, m, s);
+    assert_eq!(
+        file_name_datetime("IMG_20240501_123456"),
+        Some(at(12, 34, 56))
+    );
+    assert_eq!(
+        file_name_datetime("PXL_20240501_123456789"),
+        Some(at(12, 34, 56))
+    );
+    assert_eq!(
+        file_name_datetime("2024-05-01 12.34.56"),
+        Some(at(12, 34, 56))
+    );
+    assert_eq!(file_name_datetime("IMG-20240501-WA0001"), Some(at(0, 0, 0)));
+    assert_eq!(file_name_datetime("IMG_0001"), None);
+    assert_eq!(file_name_datetime("DSC01234"), None);
+}
+
+/// 2024-05-01T03:00:00Z
+const MOVIE_UNIX_TIME: u32 = 1_714_532_400;
+const QUICKTIME_EPOCH_OFFSET: u32 = 2_082_844_800;
+
+#[test]
+fn mp4_creation_time_epoch_is_detected() {
+    let mp4 = mp4_with(&[mvhd(MOVIE_UNIX_TIME + QUICKTIME_EPOCH_OFFSET, 10)]);
+    let capture_time = read_mp4_datetime("epoch-1904", &mp4, None);
+    assert_eq!(capture_time.source, DateSource::Mp4CreationTime);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T12:00:00+09:00"
+    );
+
+    let mp4 = mp4_with(&[mvhd(MOVIE_UNIX_TIME, 10)]);
+    assert_eq!(
+        read_mp4_datetime("epoch-1970", &mp4, None)
+            .datetime
+            .to_rfc3339(),
+        "2024-05-01T12:00:00+09:00"
+    );
+}
+
+#[test]
+fn mp4_local_creation_time_is_recognized_by_mtime() {
+    // Written as 12:00 wall-clock time rather than 03:00 UTC, closed 10 seconds later.
+    let local = MOVIE_UNIX_TIME + 9 * 3600;
+    let mp4 = mp4_with(&[mvhd(local + QUICKTIME_EPOCH_OFFSET, 10)]);
+    assert_eq!(
+        read_mp4_datetime("local", &mp4, Some(MOVIE_UNIX_TIME as u64 + 10))
+            .datetime
+            .to_rfc3339(),
+        "2024-05-01T12:00:00+09:00"
+    );
+    assert_eq!(
+        read_mp4_datetime("utc", &mp4, Some(local as u64 + 10))
+            .datetime
+            .to_rfc3339(),
+        "2024-05-01T21:00:00+09:00"
+    );
+}
+
+#[test]
+fn mp4_metadata_atoms_win_over_creation_time() {
+    let creation_time = mvhd(MOVIE_UNIX_TIME + QUICKTIME_EPOCH_OFFSET, 10);
+
+    let mp4 = mp4_with(&[
+        creation_time.clone(),
+        user_data_day("2024-05-01T05:00:00+0200"),
+        quicktime_meta(
+            "com.apple.quicktime.creationdate",
+            "2024-05-01T05:00:01+0200",
+        ),
+    ]);
+    let capture_time = read_mp4_datetime("creationdate", &mp4, None);
+    assert_eq!(capture_time.source, DateSource::QuickTimeCreationDate);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T05:00:01+02:00"
+    );
+
+    let mp4 = mp4_with(&[creation_time, user_data_day("2024-05-01T03:00:00Z")]);
+    let capture_time = read_mp4_datetime("day", &mp4, None);
+    assert_eq!(capture_time.source, DateSource::Mp4Day);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T12:00:00+09:00"
+    );
+}
+
+#[test]
+fn heif_date_comes_from_its_exif_item() {
+    let heif = heif_with_exif(&[
+        (Tag::DateTimeOriginal, "2024:05:01 12:34:56"),
+        (Tag::OffsetTimeOriginal, "+09:00"),
+    ]);
+    let path = temp_dir("heif").join("IMG_0001.HEIC");
+    fs::write(&path, &heif).unwrap();
+    let file = File::open(&path).unwrap();
+
+    let capture_time =
+        datetime(&path, &mut BufReader::new(&file), &Extension::Heif, Paris).unwrap();
+
+    assert_eq!(capture_time.source, DateSource::DateTimeOriginal);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T12:34:56+09:00"
+    );
+}
+
+fn read_movie_datetime(name: &str, data: &[u8], ext: Extension) -> CaptureTime {
+    let path = temp_dir(name).join("clip");
+    fs::write(&path, data).unwrap();
+    let file = File::open(&path).unwrap();
+    datetime(&path, &mut BufReader::new(&file), &ext, Tokyo).unwrap()
+}
+
+#[test]
+fn quicktime_family_goes_through_the_mp4_reader() {
+    let mp4 = mp4_with(&[mvhd(MOVIE_UNIX_TIME + QUICKTIME_EPOCH_OFFSET, 10)]);
+    for ext in &[Extension::Mov, Extension::M4v, Extension::ThreeGp] {
+        let capture_time = read_movie_datetime("quicktime", &mp4, *ext);
+        assert_eq!(capture_time.source, DateSource::Mp4CreationTime);
+        assert_eq!(
+            capture_time.datetime.to_rfc3339(),
+            "2024-05-01T12:00:00+09:00"
+        );
+    }
+}
+
+#[test]
+fn avi_date_comes_from_idit_then_icrd() {
+    let avi = avi_with(
+        &[riff_chunk(b"IDIT", b"WED MAY  1 12:34:56 2024\n\0")],
+        Some(&[riff_chunk(b"ICRD", b"2024-06-01 00:00:00\0")]),
+    );
+    let capture_time = read_movie_datetime("idit", &avi, Extension::Avi);
+    assert_eq!(capture_time.source, DateSource::AviIdit);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T12:34:56+09:00"
+    );
+
+    let avi = avi_with(&[], Some(&[riff_chunk(b"ICRD", b"2024:05:01 12:34:56\0")]));
+    let capture_time = read_movie_datetime("icrd", &avi, Extension::Avi);
+    assert_eq!(capture_time.source, DateSource::AviIcrd);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T12:34:56+09:00"
+    );
+
+    let avi = avi_with(&[], None);
+    assert_eq!(
+        read_movie_datetime("no-date", &avi, Extension::Avi).source,
+        DateSource::Mtime
+    );
+}
+
+fn read_raw_datetime(name: &str, data: &[u8], ext: Extension) -> (CaptureTime, Option<String>) {
+    let path = temp_dir(name).join("IMG_0001.raw");
+    fs::write(&path, data).unwrap();
+    let file = File::open(&path).unwrap();
+    let mut buff = BufReader::new(&file);
+    let (capture_time, model) = datetime_and_model(&path, &mut buff, &ext, Tokyo).unwrap();
+    assert_eq!(camera_model(&mut buff), model);
+    (capture_time, model)
+}
+
+#[test]
+fn raw_dates_come_from_tiff_and_cr3_exif() {
+    let ascii = |tag, value: &str| Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![value.as_bytes().to_vec()]),
+    };
+    let tiff = tiff_with_fields(vec![
+        ascii(Tag::Model, "DNG Camera"),
+        ascii(Tag::DateTimeOriginal, "2024:05:01 12:34:56"),
+    ]);
+    let (capture_time, model) = read_raw_datetime("dng", &tiff, Extension::Dng);
+    assert_eq!(capture_time.source, DateSource::DateTimeOriginal);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T12:34:56+09:00"
+    );
+    assert_eq!(model.as_deref(), Some("DNG Camera"));
+
+    // ORF is TIFF with its own magic number; the written TIFF is big-endian. The image data
+    // after the IFDs isn't needed for the EXIF.
+    let mut orf = tiff.clone();
+    orf[..4].copy_from_slice(b"MMOR");
+    orf.extend(vec![0xff; 1024 * 1024]);
+    let (capture_time, model) = read_raw_datetime("orf", &orf, Extension::Orf);
+    assert_eq!(capture_time.source, DateSource::DateTimeOriginal);
+    assert_eq!(model.as_deref(), Some("DNG Camera"));
+
+    let cr3 = cr3_with_exif(
+        "Canon EOS R5",
+        &[
+            (Tag::DateTimeOriginal, "2024:05:01 12:34:56"),
+            (Tag::OffsetTimeOriginal, "+02:00"),
+        ],
+    );
+    let (capture_time, model) = read_raw_datetime("cr3", &cr3, Extension::Cr3);
+    assert_eq!(capture_time.source, DateSource::DateTimeOriginal);
+    assert_eq!(
+        capture_time.datetime.to_rfc3339(),
+        "2024-05-01T12:34:56+02:00"
+    );
+    assert_eq!(model.as_deref(), Some("Canon EOS R5"));
+}
@@ -0,0 +1,246 @@
+mod common;
+
+use common::{content_hash, heif_with_exif, jpeg_with_exif, mp4_with, mvhd, temp_dir};
+use exif::Tag;
+use filetime::FileTime;
+use my_dropbox_controller::calc::{runner, Stage};
+use my_dropbox_controller::extension::Extension;
+use my_dropbox_controller::meta::DateSource;
+use my_dropbox_controller::sqlite::ScanCache;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scan_reports_unsupported_files_and_weak_dates() {
+    let dir = temp_dir("scan");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("notes.txt"), b"notes").unwrap();
+    fs::write(
+        dir.join("sub").join("IMG_20240501_123456.jpg"),
+        b"not a jpeg",
+    )
+    .unwrap();
+
+    let scan = runner(&dir, chrono_tz::Asia::Tokyo, false, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        scan.unsupported,
+        vec![dir.join("notes.txt").display().to_string()]
+    );
+    assert!(scan.failed.is_empty());
+    let files = &scan.files["2024-05-01 12:34:56"];
+    assert_eq!(files.pic.len(), 1);
+    assert_eq!(files.pic[0].date_source, DateSource::FileName);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scan_puts_quicktime_and_avi_movies_in_mov() {
+    let dir = temp_dir("scan-movies");
+    // 2024-05-01T03:00:00Z, counted from 1904.
+    let mp4 = mp4_with(&[mvhd(1_714_532_400 + 2_082_844_800, 10)]);
+    fs::write(dir.join("IMG_0001.MOV"), &mp4).unwrap();
+    fs::write(dir.join("clip.3gp"), &mp4).unwrap();
+    fs::write(dir.join("MVI_0001.AVI"), b"RIFF").unwrap();
+
+    let scan = runner(&dir, chrono_tz::Asia::Tokyo, false, None)
+        .await
+        .unwrap();
+
+    assert!(scan.unsupported.is_empty());
+    let mut names: Vec<&str> = scan.files["2024-05-01 12:00:00"]
+        .mov
+        .iter()
+        .map(|file| file.name.as_str())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["IMG_0001.MOV", "clip.3gp"]);
+    let avi = scan
+        .files
+        .values()
+        .flat_map(|files| files.mov.iter())
+        .find(|file| file.name == "MVI_0001.AVI")
+        .unwrap();
+    assert_eq!(avi.date_source, DateSource::Mtime);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scan_trusts_content_over_extension() {
+    let dir = temp_dir("scan-magic");
+    let date = [(Tag::DateTimeOriginal, "2024:05:01 12:00:00")];
+    fs::write(dir.join("IMG_0001.JPG"), heif_with_exif(&date)).unwrap();
+    fs::write(dir.join("IMG_0002.Jpeg"), jpeg_with_exif(&date)).unwrap();
+    fs::write(dir.join("IMG_0003"), jpeg_with_exif(&date)).unwrap();
+    fs::write(dir.join("screenshot.jpg"), b"\x89PNG\r\n\x1a\n").unwrap();
+
+    let scan = runner(&dir, chrono_tz::Asia::Tokyo, false, None)
+        .await
+        .unwrap();
+
+    let path = |name: &str| dir.join(name).display().to_string();
+    assert_eq!(scan.unsupported, vec![path("screenshot.jpg")]);
+    let mut mismatched = scan.mismatched.clone();
+    mismatched.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        mismatched,
+        vec![
+            (path("IMG_0001.JPG"), Extension::Jpeg, Extension::Heif),
+            (path("IMG_0003"), Extension::Other, Extension::Jpeg),
+            (path("screenshot.jpg"), Extension::Jpeg, Extension::Png),
+        ]
+    );
+    let mut pics: Vec<(&str, Extension)> = scan.files["2024-05-01 12:00:00"]
+        .pic
+        .iter()
+        .map(|file| (file.name.as_str(), file.extension))
+        .collect();
+    pics.sort_by_key(|(name, _)| *name);
+    assert_eq!(
+        pics,
+        vec![
+            ("IMG_0001.JPG", Extension::Heif),
+            ("IMG_0002.Jpeg", Extension::Jpeg),
+            ("IMG_0003", Extension::Jpeg),
+        ]
+    );
+}
+
+#[test]
+fn magic_bytes_decide_between_formats() {
+    let ftyp = |brand: &[u8]| [&b"\0\0\0\x18ftyp"[..], brand, &b"\0\0\0\0"[..]].concat();
+    assert_eq!(
+        Extension::from_magic(&ftyp(b"qt  "), Extension::Mp4),
+        Some(Extension::Mp4)
+    );
+    assert_eq!(
+        Extension::from_magic(&ftyp(b"qt  "), Extension::Jpeg),
+        Some(Extension::Mov)
+    );
+    assert_eq!(
+        Extension::from_magic(&ftyp(b"crx "), Extension::Other),
+        Some(Extension::Cr3)
+    );
+    assert_eq!(
+        Extension::from_magic(b"II*\0\x10\0\0\0CR\x02\0", Extension::Nef),
+        Some(Extension::Cr2)
+    );
+    assert_eq!(
+        Extension::from_magic(b"MM\0*\0\0\0\x08", Extension::Nef),
+        Some(Extension::Nef)
+    );
+    assert_eq!(
+        Extension::from_magic(b"MM\0*\0\0\0\x08", Extension::Jpeg),
+        Some(Extension::Tiff)
+    );
+    assert_eq!(
+        Extension::from_magic(b"RIFF\0\0\0\0WEBPVP8 ", Extension::Jpeg),
+        Some(Extension::Webp)
+    );
+    assert_eq!(Extension::from_magic(b"not a jpeg", Extension::Jpeg), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unreadable_files_are_reported_unless_strict() {
+    let dir = temp_dir("scan-unreadable");
+    fs::write(dir.join("IMG_20240501_123456.jpg"), b"not a jpeg").unwrap();
+    std::os::unix::fs::symlink(dir.join("gone.jpg"), dir.join("dangling.jpg")).unwrap();
+
+    let scan = runner(&dir, chrono_tz::Asia::Tokyo, false, None)
+        .await
+        .unwrap();
+
+    assert_eq!(scan.failed.len(), 1);
+    assert_eq!(
+        scan.failed[0].path,
+        dir.join("dangling.jpg").display().to_string()
+    );
+    assert_eq!(scan.failed[0].stage, Stage::Open);
+    assert_eq!(scan.files["2024-05-01 12:34:56"].pic.len(), 1);
+
+    let error = runner(&dir, chrono_tz::Asia::Tokyo, true, None)
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", error).contains("dangling.jpg: open:"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scan_of_more_files_than_the_channels_hold_finishes() {
+    let dir = temp_dir("scan-many");
+    for sub in 0..3 {
+        fs::create_dir_all(dir.join(sub.to_string())).unwrap();
+        for i in 0..100 {
+            let name = format!("IMG_20240501_12{:02}{:02}.jpg", sub * 2 + i / 60, i % 60);
+            fs::write(
+                dir.join(sub.to_string()).join(name),
+                format!("{}-{}", sub, i),
+            )
+            .unwrap();
+        }
+    }
+    std::os::unix::fs::symlink(dir.join("gone.jpg"), dir.join("0").join("dangling.jpg")).unwrap();
+
+    let scan = timeout(
+        Duration::from_secs(60),
+        runner(&dir, chrono_tz::Asia::Tokyo, false, None),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    let scanned: u32 = scan.files.values().map(|files| files.sum).sum();
+    assert_eq!(scanned, 300);
+    assert_eq!(scan.failed.len(), 1);
+
+    // Stopping at the failure must not leave the walker or workers waiting forever.
+    timeout(
+        Duration::from_secs(60),
+        runner(&dir, chrono_tz::Asia::Tokyo, true, None),
+    )
+    .await
+    .unwrap()
+    .unwrap_err();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unchanged_files_come_from_the_scan_cache() {
+    let dir = temp_dir("scan-cache");
+    let photos = dir.join("photos");
+    fs::create_dir_all(&photos).unwrap();
+    let photo = photos.join("IMG_0001.JPG");
+    let date = |value| jpeg_with_exif(&[(Tag::DateTimeOriginal, value)]);
+    fs::write(&photo, date("2024:05:01 12:00:00")).unwrap();
+    let db_path = dir.join("index.db3").display().to_string();
+    let scan = |rehash| {
+        let cache = Arc::new(ScanCache::open(&db_path, rehash).unwrap());
+        runner(&photos, chrono_tz::Asia::Tokyo, false, Some(cache))
+    };
+
+    let first = scan(false).await.unwrap();
+    assert!(first.files.contains_key("2024-05-01 12:00:00"));
+
+    // Same size, inode and mtime: the cache can't tell it changed.
+    let mtime = fs::metadata(&photo).unwrap().modified().unwrap();
+    let edited = date("2024:05:02 12:00:00");
+    fs::write(&photo, &edited).unwrap();
+    filetime::set_file_mtime(&photo, FileTime::from_system_time(mtime)).unwrap();
+    let cached = scan(false).await.unwrap();
+    let files = &cached.files["2024-05-01 12:00:00"];
+    assert_eq!(
+        files.pic[0].digest,
+        first.files["2024-05-01 12:00:00"].pic[0].digest
+    );
+    assert_eq!(files.pic[0].date_source, DateSource::DateTimeOriginal);
+    assert_eq!(files.pic[0].extension, Extension::Jpeg);
+
+    let rehashed = scan(true).await.unwrap();
+    let files = &rehashed.files["2024-05-02 12:00:00"];
+    assert_eq!(files.pic[0].digest, content_hash(&edited));
+
+    // A new mtime is enough to read the file again.
+    fs::write(&photo, date("2024:05:03 12:00:00")).unwrap();
+    let changed = scan(false).await.unwrap();
+    assert!(changed.files.contains_key("2024-05-03 12:00:00"));
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::temp_dir;
+use my_dropbox_controller::sqlite::connection;
+use rusqlite::{params, Connection, NO_PARAMS};
+
+fn user_version(conn: &Connection) -> i64 {
+    conn.query_row("PRAGMA user_version;", NO_PARAMS, |row| row.get(0))
+        .unwrap()
+}
+
+#[test]
+fn legacy_index_is_migrated_in_place() {
+    let dir = temp_dir("migrate");
+    let db_path = dir.join("index.db3").display().to_string();
+    let legacy = Connection::open(&db_path).unwrap();
+    legacy
+        .execute_batch(
+            "CREATE TABLE files (name TEXT, hash TEXT, path TEXT, size INTEGER, uploaded_at TEXT);
+            INSERT INTO files (name, hash) VALUES ('a.jpg', 'aaa');
+            INSERT INTO files (name, hash) VALUES ('a copy.jpg', 'aaa');
+            INSERT INTO files (name, hash, path, size, uploaded_at)
+            VALUES ('b.mp4', 'bbb', '/カメラアップロード/b.mp4', 42, '2024-05-01T00:00:00+00:00');",
+        )
+        .unwrap();
+    drop(legacy);
+
+    let conn = connection(&db_path).unwrap();
+
+    assert_eq!(user_version(&conn), 6);
+    let rows: Vec<(String, String, Option<String>, Option<i64>)> = conn
+        .prepare("SELECT name, content_hash, path, size FROM files ORDER BY name;")
+        .unwrap()
+        .query_map(NO_PARAMS, |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            ("a.jpg".to_string(), "aaa".to_string(), None, None),
+            (
+                "b.mp4".to_string(),
+                "bbb".to_string(),
+                Some("/カメラアップロード/b.mp4".to_string()),
+                Some(42)
+            ),
+        ]
+    );
+    assert!(conn
+        .execute(
+            "INSERT INTO files (name, content_hash) VALUES (?1, ?2);",
+            params!["again.jpg", "aaa"],
+        )
+        .is_err());
+
+    // Already current: opening again changes nothing.
+    drop(conn);
+    let conn = connection(&db_path).unwrap();
+    assert_eq!(user_version(&conn), 6);
+}
+
+#[test]
+fn oldest_and_newer_indexes_are_handled() {
+    let dir = temp_dir("migrate-versions");
+    let oldest = dir.join("oldest.db3").display().to_string();
+    Connection::open(&oldest)
+        .unwrap()
+        .execute_batch(
+            "CREATE TABLE files (name TEXT, hash TEXT);
+            INSERT INTO files (name, hash) VALUES ('a.jpg', 'aaa');",
+        )
+        .unwrap();
+    let conn = connection(&oldest).unwrap();
+    let count: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM files WHERE content_hash = 'aaa' AND path IS NULL;",
+            NO_PARAMS,
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(count, 1);
+
+    let newer = dir.join("newer.db3").display().to_string();
+    Connection::open(&newer)
+        .unwrap()
+        .execute_batch("PRAGMA user_version = 99;")
+        .unwrap();
+    let error = connection(&newer).unwrap_err();
+    assert!(error.to_string().contains("newer"));
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::{content_hash, temp_dir};
+use my_dropbox_controller::calc::{DatetimeExtnameDigests, NameDigest, SumNameDigests};
+use my_dropbox_controller::config::Config;
+use my_dropbox_controller::dropbox::upload_files;
+use my_dropbox_controller::extension::Extension;
+use my_dropbox_controller::meta::DateSource;
+use my_dropbox_controller::sqlite::reset_db;
+use my_dropbox_controller::store::{FinishBatchStatus, FinishEntry, LocalStore, RemoteStore};
+use rusqlite::{Connection, NO_PARAMS};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+fn name_digest(path: &Path, data: &[u8]) -> NameDigest {
+    fs::write(path, data).unwrap();
+    NameDigest {
+        digest: content_hash(data),
+        name: path.file_name().unwrap().to_str().unwrap().to_string(),
+        path: path.display().to_string(),
+        model: None,
+        date_source: DateSource::DateTimeOriginal,
+        extension: Extension::from_path(path).unwrap(),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upload_and_reset_db_against_a_local_store() {
+    let dir = temp_dir("local-store");
+    let root = dir.join("remote");
+    let store: Arc<dyn RemoteStore> = Arc::new(LocalStore::new(&root).unwrap());
+    // Larger than one 4 MiB block, so the session gets appended at two offsets.
+    let movie: Vec<u8> = (0..5 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let mut files: DatetimeExtnameDigests = HashMap::new();
+    files.insert(
+        "2021-05-01 12:00:00".to_string(),
+        SumNameDigests {
+            pic: vec![name_digest(&dir.join("new.jpg"), b"new")],
+            mov: vec![name_digest(&dir.join("movie.mp4"), &movie)],
+            sum: 2,
+        },
+    );
+
+    let upload_db = dir.join("upload.db3").display().to_string();
+    let report = upload_files(store.clone(), &upload_db, files, &Config::default())
+        .await
+        .unwrap();
+
+    assert!(report.failed.is_empty());
+    let uploaded = root.join("カメラアップロード");
+    assert_eq!(
+        fs::read(uploaded.join("2021-05-01 12:00:00.jpg")).unwrap(),
+        b"new"
+    );
+    assert_eq!(
+        fs::read(uploaded.join("2021-05-01 12:00:00.mp4")).unwrap(),
+        movie
+    );
+
+    let reset_path = dir.join("reset.db3").display().to_string();
+    reset_db(store, &reset_path, "/カメラアップロード")
+        .await
+        .unwrap();
+    let conn = Connection::open(&reset_path).unwrap();
+    let mut statement = conn
+        .prepare("SELECT path, content_hash FROM files ORDER BY path;")
+        .unwrap();
+    let rows: Vec<(String, String)> = statement
+        .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            (
+                "/カメラアップロード/2021-05-01 12:00:00.jpg".to_string(),
+                content_hash(b"new")
+            ),
+            (
+                "/カメラアップロード/2021-05-01 12:00:00.mp4".to_string(),
+                content_hash(&movie)
+            ),
+        ]
+    );
+}
+
+#[test]
+fn local_store_rejects_paths_outside_its_root() {
+    let dir = temp_dir("local-store-escape");
+    let store = LocalStore::new(&dir.join("remote")).unwrap();
+    let session_id = store.upload_session_start().unwrap();
+    store
+        .upload_session_append(&session_id, 0, b"escaped", true)
+        .unwrap();
+
+    let status = store
+        .finish_batch(&[FinishEntry {
+            session_id,
+            offset: 7,
+            path: "/../escaped.jpg".to_string(),
+            overwrite: false,
+        }])
+        .unwrap();
+
+    match status {
+        FinishBatchStatus::Complete(results) => {
+            assert!(results[0]
+                .as_ref()
+                .unwrap_err()
+                .contains("invalid remote path"))
+        }
+        status => panic!("unexpected status: {:?}", status),
+    }
+    assert!(!dir.join("escaped.jpg").exists());
+    assert!(store.get_metadata("/../escaped.jpg").is_err());
+    assert!(store.download("/a/../../escaped.jpg").is_err());
+    assert!(store.list_folder("/..", true).is_err());
+}
@@ -0,0 +1,682 @@
+mod common;
+
+use common::{content_hash, temp_dir, MockDropbox};
+use my_dropbox_controller::auth::StaticToken;
+use my_dropbox_controller::calc::{DatetimeExtnameDigests, NameDigest, SumNameDigests};
+use my_dropbox_controller::config::Config;
+use my_dropbox_controller::dropbox::{plan_upload, upload_files, DropboxStore, UploadError};
+use my_dropbox_controller::extension::Extension;
+use my_dropbox_controller::limit::UploadLimiter;
+use my_dropbox_controller::meta::DateSource;
+use my_dropbox_controller::retry::RetryPolicy;
+use my_dropbox_controller::sqlite::{
+    connection, pull_index, push_index, reset_db, sync_db, FileStamp, SessionJournal,
+};
+use my_dropbox_controller::store::{LocalStore, RemoteStore};
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn name_digest(path: &Path, data: &[u8]) -> NameDigest {
+    fs::write(path, data).unwrap();
+    NameDigest {
+        digest: content_hash(data),
+        name: path.file_name().unwrap().to_str().unwrap().to_string(),
+        path: path.display().to_string(),
+        model: None,
+        date_source: DateSource::DateTimeOriginal,
+        extension: Extension::from_path(path).unwrap(),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upload_commits_new_files_and_skips_known_ones() {
+    let server = MockDropbox::start();
+    server.set_pending_polls(2);
+    let store = Arc::new(DropboxStore::with_base_url(
+        Arc::new(StaticToken("token".to_string())),
+        server.url(),
+    ));
+    let dir = temp_dir("upload");
+    let db_path = dir.join("index.db3").display().to_string();
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute("CREATE TABLE files (name TEXT, hash TEXT);", NO_PARAMS)
+        .unwrap();
+    conn.execute(
+        "INSERT INTO files (name, hash) VALUES (?1, ?2);",
+        params!["known.jpg", content_hash(b"known")],
+    )
+    .unwrap();
+
+    // Larger than one 4 MiB block, so the session gets appended at two offsets.
+    let movie: Vec<u8> = (0..5 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let mut files: DatetimeExtnameDigests = HashMap::new();
+    files.insert(
+        "2021-05-01 12:00:00".to_string(),
+        SumNameDigests {
+            pic: vec![
+                name_digest(&dir.join("known.jpg"), b"known"),
+                name_digest(&dir.join("new.jpg"), b"new"),
+            ],
+            mov: vec![name_digest(&dir.join("movie.mp4"), &movie)],
+            sum: 3,
+        },
+    );
+
+    upload_files(store, &db_path, files, &Config::default())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.paths(),
+        vec![
+            "/カメラアップロード/2021-05-01 12:00:00.jpg".to_string(),
+            "/カメラアップロード/2021-05-01 12:00:00.mp4".to_string(),
+        ]
+    );
+    assert_eq!(
+        server.file("/カメラアップロード/2021-05-01 12:00:00.jpg"),
+        Some(b"new".to_vec())
+    );
+    assert_eq!(
+        server.file("/カメラアップロード/2021-05-01 12:00:00.mp4"),
+        Some(movie)
+    );
+    assert_eq!(server.calls("files/upload_session/finish_batch"), 1);
+    assert_eq!(server.calls("files/upload_session/finish_batch/check"), 3);
+
+    let (path, size, file_type, capture_time, rev): (String, i64, String, String, String) = conn
+        .query_row(
+            "SELECT path, size, file_type, capture_time, rev FROM files
+            WHERE content_hash = ?1 AND uploaded_at IS NOT NULL;",
+            params![content_hash(b"new")],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )
+        .unwrap();
+    assert_eq!(path, "/カメラアップロード/2021-05-01 12:00:00.jpg");
+    assert_eq!(size, 3);
+    assert_eq!(file_type, "picture");
+    assert_eq!(capture_time, "2021-05-01 12:00:00");
+    assert_eq!(rev, "015c0b9c1c0d4d50000000001");
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM files;", NO_PARAMS, |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upload_resumes_journaled_session() {
+    let server = MockDropbox::start();
+    let store = Arc::new(DropboxStore::with_base_url(
+        Arc::new(StaticToken("token".to_string())),
+        server.url(),
+    ));
+    let dir = temp_dir("resume");
+    let db_path = dir.join("index.db3").display().to_string();
+    Connection::open(&db_path)
+        .unwrap()
+        .execute("CREATE TABLE files (name TEXT, hash TEXT);", NO_PARAMS)
+        .unwrap();
+    let block_size = 4 * 1024 * 1024;
+    let movie: Vec<u8> = (0..block_size + 1024).map(|i| (i % 251) as u8).collect();
+    let movie_digest = name_digest(&dir.join("movie.mp4"), &movie);
+
+    // A previous run that died after its first block was acknowledged.
+    let session_id = store.upload_session_start().unwrap();
+    store
+        .upload_session_append(&session_id, 0, &movie[..block_size], false)
+        .unwrap();
+    let journal = SessionJournal::open(&db_path).unwrap();
+    let movie_path = dir.join("movie.mp4");
+    journal
+        .start(
+            &movie_digest.path,
+            FileStamp::of(&movie_path).unwrap(),
+            &session_id,
+        )
+        .unwrap();
+    journal
+        .update(&session_id, block_size as u64, &HashMap::new())
+        .unwrap();
+
+    let mut files: DatetimeExtnameDigests = HashMap::new();
+    files.insert(
+        "2021-05-01 12:00:00".to_string(),
+        SumNameDigests {
+            pic: Vec::new(),
+            mov: vec![movie_digest],
+            sum: 1,
+        },
+    );
+    upload_files(store, &db_path, files, &Config::default())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.file("/カメラアップロード/2021-05-01 12:00:00.mp4"),
+        Some(movie)
+    );
+    assert_eq!(server.calls("files/upload_session/start"), 1);
+    assert_eq!(server.calls("files/upload_session/append_v2"), 2);
+    assert!(journal
+        .find(
+            &movie_path.display().to_string(),
+            FileStamp::of(&movie_path).unwrap()
+        )
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upload_starts_over_when_journaled_session_is_gone() {
+    let server = MockDropbox::start();
+    let store = Arc::new(DropboxStore::with_base_url(
+        Arc::new(StaticToken("token".to_string())),
+        server.url(),
+    ));
+    let dir = temp_dir("resume-gone");
+    let db_path = dir.join("index.db3").display().to_string();
+    let photo = name_digest(&dir.join("a.jpg"), b"photo");
+    let photo_path = photo.path.clone();
+    // A session from an earlier run that Dropbox has since expired.
+    let journal = SessionJournal::open(&db_path).unwrap();
+    let stamp = FileStamp::of(&dir.join("a.jpg")).unwrap();
+    journal
+        .start(&photo.path, stamp, "session-expired")
+        .unwrap();
+
+    let mut files: DatetimeExtnameDigests = HashMap::new();
+    files.insert(
+        "2021-05-01 12:00:00".to_string(),
+        SumNameDigests {
+            pic: vec![photo],
+            mov: Vec::new(),
+            sum: 1,
+        },
+    );
+    let report = upload_files(store, &db_path, files, &Config::default())
+        .await
+        .unwrap();
+
+    assert!(report.failed.is_empty(), "{:?}", report.failed);
+    assert_eq!(
+        server.file("/カメラアップロード/2021-05-01 12:00:00.jpg"),
+        Some(b"photo".to_vec())
+    );
+    assert_eq!(server.calls("files/upload_session/start"), 1);
+    assert!(journal.find(&photo_path, stamp).unwrap().is_none());
+}
+
+#[test]
+fn journaled_session_is_not_resumed_for_a_replaced_file() {
+    let dir = temp_dir("resume-replaced");
+    let db_path = dir.join("index.db3").display().to_string();
+    let path = dir.join("a.jpg");
+    fs::write(&path, b"first").unwrap();
+    let journal = SessionJournal::open(&db_path).unwrap();
+    let stamp = FileStamp::of(&path).unwrap();
+    let key = path.display().to_string();
+    journal.start(&key, stamp, "session-1").unwrap();
+    assert!(journal.find(&key, stamp).unwrap().is_some());
+
+    // Same path and size, different file.
+    let replacement = dir.join("a.jpg.new");
+    fs::write(&replacement, b"other").unwrap();
+    fs::rename(&replacement, &path).unwrap();
+
+    let replaced = FileStamp::of(&path).unwrap();
+    assert_eq!(replaced.size, stamp.size);
+    assert!(journal.find(&key, replaced).unwrap().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upload_reports_conflicting_entry_as_failed() {
+    let server = MockDropbox::start();
+    server.put_file("/カメラアップロード/2021-05-01 12:00:00.jpg", b"other");
+    let store = Arc::new(DropboxStore::with_base_url(
+        Arc::new(StaticToken("token".to_string())),
+        server.url(),
+    ));
+    let dir = temp_dir("conflict");
+    let db_path = dir.join("index.db3").display().to_string();
+    let conflicting = name_digest(&dir.join("a.jpg"), b"a");
+    let conflicting_path = conflicting.path.clone();
+    let mut files: DatetimeExtnameDigests = HashMap::new();
+    files.insert(
+        "2021-05-01 12:00:00".to_string(),
+        SumNameDigests {
+            pic: vec![conflicting, name_digest(&dir.join("b.jpg"), b"b")],
+            mov: Vec::new(),
+            sum: 2,
+        },
+    );
+
+    let report = upload_files(store, &db_path, files, &Config::default())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        report.uploaded,
+        vec![dir.join("b.jpg").display().to_string()]
+    );
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, conflicting_path);
+    assert!(matches!(report.failed[0].1, UploadError::EntryError { .. }));
+    assert_eq!(
+        server.file("/カメラアップロード/2021-05-01 12:00:00.jpg"),
+        Some(b"other".to_vec())
+    );
+    let count: i64 = Connection::open(&db_path)
+        .unwrap()
+        .query_row("SELECT COUNT(*) FROM files;", NO_PARAMS, |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rate_limited_requests_are_retried_up_to_max_attempts() {
+    let server = MockDropbox::start();
+    server.set_rate_limited("files/upload_session/append_v2", 2);
+    server.set_rate_limited("files/upload_session/finish_batch", 1);
+    let store = Arc::new(
+        DropboxStore::with_base_url(Arc::new(StaticToken("token".to_string())), server.url())
+            .with_retry(RetryPolicy::default().with_max_attempts(3)),
+    );
+    let dir = temp_dir("rate-limit");
+    let db_path = dir.join("index.db3").display().to_string();
+    let mut files: DatetimeExtnameDigests = HashMap::new();
+    files.insert(
+        "2021-05-01 12:00:00".to_string(),
+        SumNameDigests {
+            pic: vec![name_digest(&dir.join("a.jpg"), b"a")],
+            mov: Vec::new(),
+            sum: 1,
+        },
+    );
+
+    let report = upload_files(store.clone(), &db_path, files, &Config::default())
+        .await
+        .unwrap();
+
+    assert_eq!(report.failed.len(), 0);
+    assert_eq!(
+        server.file("/カメラアップロード/2021-05-01 12:00:00.jpg"),
+        Some(b"a".to_vec())
+    );
+    assert_eq!(server.calls("files/upload_session/append_v2"), 3);
+    assert_eq!(server.calls("files/upload_session/finish_batch"), 2);
+
+    server.set_rate_limited("files/upload_session/start", 3);
+    let mut files: DatetimeExtnameDigests = HashMap::new();
+    files.insert(
+        "2021-05-02 12:00:00".to_string(),
+        SumNameDigests {
+            pic: vec![name_digest(&dir.join("b.jpg"), b"b")],
+            mov: Vec::new(),
+            sum: 1,
+        },
+    );
+
+    let report = upload_files(store, &db_path, files, &Config::default())
+        .await
+        .unwrap();
+
+    assert_eq!(report.failed.len(), 1);
+    assert!(matches!(
+        report.failed[0].1,
+        UploadError::SessionError { .. }
+    ));
+    assert_eq!(server.calls("files/upload_session/start"), 4);
+}
+
+#[test]
+fn retried_appends_are_paced_by_the_limiter() {
+    let server = MockDropbox::start();
+    server.set_rate_limited("files/upload_session/append_v2", 2);
+    let limiter = Arc::new(UploadLimiter::new(None, Some(100_000)));
+    let store =
+        DropboxStore::with_base_url(Arc::new(StaticToken("token".to_string())), server.url())
+            .with_retry(RetryPolicy::default().with_max_attempts(3))
+            .with_limiter(limiter);
+    let session_id = store.upload_session_start().unwrap();
+    let start = Instant::now();
+
+    store
+        .upload_session_append(&session_id, 0, &[0; 10_000], false)
+        .unwrap();
+
+    // Each of the three attempts is charged 0.1s of the budget.
+    assert_eq!(server.calls("files/upload_session/append_v2"), 3);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn plan_names_new_files_and_lists_duplicates() {
+    let dir = temp_dir("plan");
+    let db_path = dir.join("index.db3").display().to_string();
+    let conn = connection(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO files (name, content_hash) VALUES (?1, ?2);",
+        params!["known.jpg", content_hash(b"known")],
+    )
+    .unwrap();
+    let mut files: DatetimeExtnameDigests = HashMap::new();
+    files.insert(
+        "2021-05-01 12:00:00".to_string(),
+        SumNameDigests {
+            pic: vec![
+                name_digest(&dir.join("known.jpg"), b"known"),
+                name_digest(&dir.join("a.jpg"), b"a"),
+                name_digest(&dir.join("b.jpg"), b"b"),
+                name_digest(&dir.join("d.heic"), b"d"),
+            ],
+            mov: vec![name_digest(&dir.join("c.mp4"), b"c")],
+            sum: 5,
+        },
+    );
+
+    let plan = plan_upload(&db_path, &files, &Config::default()).unwrap();
+
+    let path = |name: &str| dir.join(name).display().to_string();
+    assert_eq!(
+        plan.new,
+        vec![
+            (
+                path("a.jpg"),
+                "/カメラアップロード/2021-05-01 12:00:00.jpg".to_string()
+            ),
+            (
+                path("b.jpg"),
+                "/カメラアップロード/2021-05-01 12:00:00_1.jpg".to_string()
+            ),
+            (
+                path("d.heic"),
+                "/カメラアップロード/2021-05-01 12:00:00.heic".to_string()
+            ),
+            (
+                path("c.mp4"),
+                "/カメラアップロード/2021-05-01 12:00:00.mp4".to_string()
+            ),
+        ]
+    );
+    assert_eq!(plan.duplicates, vec![path("known.jpg")]);
+}
+
+#[test]
+fn plan_gives_raw_and_jpeg_pairs_matching_names() {
+    let dir = temp_dir("plan-raw");
+    let db_path = dir.join("index.db3").display().to_string();
+    let mut files: DatetimeExtnameDigests = HashMap::new();
+    files.insert(
+        "2024-05-01 12:00:00".to_string(),
+        SumNameDigests {
+            pic: vec![
+                name_digest(&dir.join("A.jpg"), b"a"),
+                name_digest(&dir.join("B.dng"), b"b raw"),
+                name_digest(&dir.join("B.JPG"), b"b"),
+                name_digest(&dir.join("C.dng"), b"c raw"),
+            ],
+            mov: Vec::new(),
+            sum: 4,
+        },
+    );
+
+    let plan = plan_upload(&db_path, &files, &Config::default()).unwrap();
+
+    let path = |name: &str| dir.join(name).display().to_string();
+    let remote = |name: &str| format!("/カメラアップロード/2024-05-01 12:00:00{}", name);
+    assert_eq!(
+        plan.new,
+        vec![
+            (path("A.jpg"), remote(".jpg")),
+            (path("B.JPG"), remote("_1.jpg")),
+            (path("B.dng"), remote("_1.dng")),
+            (path("C.dng"), remote("_2.dng")),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reset_db_follows_list_folder_cursor() {
+    let server = MockDropbox::start();
+    server.set_page_size(2);
+    for i in 0..5 {
+        server.put_file(&format!("/photos/{}.jpg", i), format!("{}", i).as_bytes());
+    }
+    let store = Arc::new(DropboxStore::with_base_url(
+        Arc::new(StaticToken("token".to_string())),
+        server.url(),
+    ));
+    let dir = temp_dir("reset-db");
+    let db_path = dir.join("index.db3").display().to_string();
+
+    reset_db(store, &db_path, "/photos").await.unwrap();
+
+    let conn = Connection::open(&db_path).unwrap();
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM files;", NO_PARAMS, |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, 5);
+    assert_eq!(server.calls("files/list_folder/continue"), 2);
+}
+
+fn names(conn: &Connection) -> Vec<String> {
+    conn.prepare("SELECT name FROM files ORDER BY name;")
+        .unwrap()
+        .query_map(NO_PARAMS, |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn count(conn: &Connection, table: &str) -> i64 {
+    conn.query_row(
+        &format!("SELECT COUNT(*) FROM {};", table),
+        NO_PARAMS,
+        |row| row.get(0),
+    )
+    .unwrap()
+}
+
+#[test]
+fn index_is_pushed_and_pulled_without_local_state() {
+    let server = MockDropbox::start();
+    let store: Arc<dyn RemoteStore> = Arc::new(DropboxStore::with_base_url(
+        Arc::new(StaticToken("token".to_string())),
+        server.url(),
+    ));
+    let dir = temp_dir("index-sync");
+    let pushed = dir.join("pushed.db3").display().to_string();
+    let conn = connection(&pushed).unwrap();
+    conn.execute(
+        "INSERT INTO files (name, content_hash) VALUES (?1, ?2);",
+        params!["a.jpg", "aaa"],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO upload_sessions
+        (session_id, path, file_size, complete_up_to, uploaded_blocks, started_at)
+        VALUES ('session-1', '/local/a.jpg', 10, 4, '[]', 0);",
+        NO_PARAMS,
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO list_cursor VALUES ('/photos', 'cursor-1');",
+        NO_PARAMS,
+    )
+    .unwrap();
+
+    push_index(store.clone(), &pushed, "/my-dropbox2.db3").unwrap();
+    // A second push replaces the first.
+    conn.execute(
+        "INSERT INTO files (name, content_hash) VALUES (?1, ?2);",
+        params!["b.jpg", "bbb"],
+    )
+    .unwrap();
+    push_index(store.clone(), &pushed, "/my-dropbox2.db3").unwrap();
+
+    let copy = dir.join("copy.db3");
+    fs::write(&copy, server.file("/my-dropbox2.db3").unwrap()).unwrap();
+    let copy = Connection::open(&copy).unwrap();
+    assert_eq!(names(&copy), vec!["a.jpg", "b.jpg"]);
+    assert_eq!(count(&copy, "upload_sessions"), 0);
+
+    let pulled = dir.join("pulled.db3").display().to_string();
+    let conn = connection(&pulled).unwrap();
+    conn.execute(
+        "INSERT INTO files (name, content_hash) VALUES (?1, ?2);",
+        params!["stale.jpg", "sss"],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO upload_sessions
+        (session_id, path, file_size, complete_up_to, uploaded_blocks, started_at)
+        VALUES ('session-2', '/local/c.jpg', 10, 4, '[]', 0);",
+        NO_PARAMS,
+    )
+    .unwrap();
+
+    pull_index(store.as_ref(), "/my-dropbox2.db3", &pulled).unwrap();
+
+    assert_eq!(names(&conn), vec!["a.jpg", "b.jpg"]);
+    assert_eq!(count(&conn, "upload_sessions"), 1);
+    let cursor: String = conn
+        .query_row("SELECT cursor FROM list_cursor;", NO_PARAMS, |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(cursor, "cursor-1");
+    let error = pull_index(store.as_ref(), "/missing.db3", &pulled).unwrap_err();
+    assert!(error.to_string().contains("no index"));
+    assert_eq!(names(&conn), vec!["a.jpg", "b.jpg"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sync_applies_changes_since_reset_db() {
+    let server = MockDropbox::start();
+    server.set_page_size(2);
+    for name in &["a.jpg", "b.jpg", "c.jpg", "old/d.jpg"] {
+        server.put_file(&format!("/photos/{}", name), name.as_bytes());
+    }
+    let store: Arc<dyn RemoteStore> = Arc::new(DropboxStore::with_base_url(
+        Arc::new(StaticToken("token".to_string())),
+        server.url(),
+    ));
+    let dir = temp_dir("sync-db");
+    let db_path = dir.join("index.db3").display().to_string();
+    let error = sync_db(store.as_ref(), &db_path).unwrap_err();
+    assert!(error.to_string().contains("reset-db"));
+    reset_db(store.clone(), &db_path, "/photos").await.unwrap();
+
+    server.put_file("/photos/a.jpg", b"edited");
+    server.put_file("/photos/e.jpg", b"e");
+    server.delete("/photos/b.jpg");
+    server.delete("/photos/old");
+    server.put_file("/elsewhere/f.jpg", b"f");
+    let report = sync_db(store.as_ref(), &db_path).unwrap();
+
+    assert_eq!((report.updated, report.deleted), (2, 2));
+    let conn = Connection::open(&db_path).unwrap();
+    assert_eq!(names(&conn), vec!["a.jpg", "c.jpg", "e.jpg"]);
+    let hash: String = conn
+        .query_row(
+            "SELECT content_hash FROM files WHERE name = 'a.jpg';",
+            NO_PARAMS,
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(hash, content_hash(b"edited"));
+
+    let report = sync_db(store.as_ref(), &db_path).unwrap();
+    assert_eq!((report.updated, report.deleted), (0, 0));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sync_moves_the_row_to_a_remaining_copy() {
+    let server = MockDropbox::start();
+    server.put_file("/photos/a.jpg", b"a");
+    server.put_file("/photos/b.jpg", b"b");
+    let store: Arc<dyn RemoteStore> = Arc::new(DropboxStore::with_base_url(
+        Arc::new(StaticToken("token".to_string())),
+        server.url(),
+    ));
+    let dir = temp_dir("sync-copies");
+    let db_path = dir.join("index.db3").display().to_string();
+    reset_db(store.clone(), &db_path, "/photos").await.unwrap();
+    let conn = Connection::open(&db_path).unwrap();
+    let indexed_path = |data: &[u8]| -> Option<String> {
+        conn.query_row(
+            "SELECT path FROM files WHERE content_hash = ?1;",
+            params![content_hash(data)],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap()
+    };
+
+    // A copy doesn't take over the indexed path.
+    server.put_file("/photos/copy/a.jpg", b"a");
+    let report = sync_db(store.as_ref(), &db_path).unwrap();
+    assert_eq!((report.updated, report.deleted), (0, 0));
+    assert_eq!(indexed_path(b"a").as_deref(), Some("/photos/a.jpg"));
+
+    // Deleting the indexed path moves the row to the copy.
+    server.delete("/photos/a.jpg");
+    let report = sync_db(store.as_ref(), &db_path).unwrap();
+    assert_eq!((report.updated, report.deleted), (0, 0));
+    assert_eq!(indexed_path(b"a").as_deref(), Some("/photos/copy/a.jpg"));
+
+    // So does rewriting it.
+    server.put_file("/photos/c.jpg", b"a");
+    server.put_file("/photos/copy/a.jpg", b"edited");
+    let report = sync_db(store.as_ref(), &db_path).unwrap();
+    assert_eq!((report.updated, report.deleted), (1, 0));
+    assert_eq!(indexed_path(b"a").as_deref(), Some("/photos/c.jpg"));
+    assert_eq!(
+        indexed_path(b"edited").as_deref(),
+        Some("/photos/copy/a.jpg")
+    );
+
+    // With no copy left, the row goes.
+    server.delete("/photos/c.jpg");
+    let report = sync_db(store.as_ref(), &db_path).unwrap();
+    assert_eq!((report.updated, report.deleted), (0, 1));
+    assert_eq!(indexed_path(b"a"), None);
+    assert_eq!(count(&conn, "file_copies"), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn local_store_refuses_incremental_sync() {
+    let dir = temp_dir("sync-local");
+    let store: Arc<dyn RemoteStore> = Arc::new(LocalStore::new(&dir.join("remote")).unwrap());
+    let photos = dir.join("remote").join("photos");
+    fs::create_dir_all(&photos).unwrap();
+    fs::write(photos.join("a.jpg"), b"a").unwrap();
+    let db_path = dir.join("index.db3").display().to_string();
+    reset_db(store.clone(), &db_path, "/photos").await.unwrap();
+
+    fs::write(photos.join("a.jpg"), b"edited").unwrap();
+    let error = sync_db(store.as_ref(), &db_path).unwrap_err();
+
+    assert!(error
+        .to_string()
+        .contains("incremental sync not supported for local store"));
+    let conn = Connection::open(&db_path).unwrap();
+    let hash: String = conn
+        .query_row("SELECT content_hash FROM files;", NO_PARAMS, |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(hash, content_hash(b"a"));
+}